@@ -1,7 +1,10 @@
 use proc_macro as pm;
 use proc_macro2 as pm2;
-use quote::quote;
-use syn::{parse_macro_input, FnArg, Meta, NestedMeta, Pat};
+use quote::{format_ident, quote};
+use syn::{
+	ext::IdentExt, parse_macro_input, Data, DeriveInput, Fields, FnArg, Meta, NestedMeta,
+	Pat, ReturnType, Type,
+};
 
 #[proc_macro_attribute]
 pub fn trace(args: pm::TokenStream, input: pm::TokenStream) -> pm::TokenStream {
@@ -44,3 +47,303 @@ pub fn trace(args: pm::TokenStream, input: pm::TokenStream) -> pm::TokenStream {
 
 	result.into()
 }
+
+/// Turns a plain Rust function into a `vm::Native` constant with the same name, to be
+/// defined as a global with `VM::register_native`.
+///
+/// Arguments are converted with `FromLox` and the return value with `IntoLox`, so they
+/// can't be references. The exception is a first parameter of `&mut VM`, which the
+/// calling VM is passed in as, without counting towards the arity. A function
+/// returning a `Result` can fail with any error that converts into a `vm::Error`,
+/// such as a `vm::ConversionError`.
+///
+/// With `#[native(method)]`, the next parameter is the method's receiver (`this`),
+/// which doesn't count towards the arity either. Methods are defined on a class with
+/// `VM::define_class`.
+#[proc_macro_attribute]
+pub fn native(args: pm::TokenStream, input: pm::TokenStream) -> pm::TokenStream {
+	let args = parse_macro_input!(args as syn::AttributeArgs);
+	let ast = parse_macro_input!(input as syn::ItemFn);
+
+	match expand_native(args, ast) {
+		Ok(result) => result.into(),
+		Err(err) => err.to_compile_error().into(),
+	}
+}
+
+fn expand_native(
+	args: syn::AttributeArgs,
+	ast: syn::ItemFn,
+) -> syn::Result<pm2::TokenStream> {
+	let mut is_method = false;
+	for arg in args.iter() {
+		match arg {
+			NestedMeta::Meta(Meta::Path(path)) if path.is_ident("method") => {
+				is_method = true;
+			}
+			_ => {
+				return Err(syn::Error::new_spanned(
+					arg,
+					"Unknown `#[native]` option, expected `method`",
+				));
+			}
+		}
+	}
+
+	let sig = &ast.sig;
+	if let Some(asyncness) = &sig.asyncness {
+		return Err(syn::Error::new_spanned(
+			asyncness,
+			"`#[native]` functions can't be async",
+		));
+	}
+	if !sig.generics.params.is_empty() {
+		return Err(syn::Error::new_spanned(
+			&sig.generics,
+			"`#[native]` functions can't be generic",
+		));
+	}
+
+	let mut args = vec![];
+	for arg in sig.inputs.iter() {
+		match arg {
+			FnArg::Receiver(receiver) => {
+				return Err(syn::Error::new_spanned(
+					receiver,
+					"`#[native]` functions can't take `self`",
+				));
+			}
+			FnArg::Typed(arg) => args.push(arg),
+		}
+	}
+
+	let takes_vm = matches!(
+		args.first().map(|arg| arg.ty.as_ref()),
+		Some(Type::Reference(ty)) if ty.mutability.is_some() && is_vm(&ty.elem)
+	);
+	if takes_vm {
+		args.remove(0);
+	}
+	// Everything else is converted from a Lox value, so there's nothing to borrow
+	if let Some(arg) = args
+		.iter()
+		.find(|arg| matches!(arg.ty.as_ref(), Type::Reference(_)))
+	{
+		return Err(syn::Error::new_spanned(
+			arg,
+			"`#[native]` functions can't take references, other than a leading `&mut VM`",
+		));
+	}
+
+	let types: Vec<_> = args.iter().map(|arg| arg.ty.as_ref()).collect();
+	if is_method && types.is_empty() {
+		return Err(syn::Error::new_spanned(
+			&sig.inputs,
+			"`#[native(method)]` functions must take a receiver",
+		));
+	}
+	if types.len() - is_method as usize > u8::MAX as usize {
+		return Err(syn::Error::new_spanned(
+			&sig.inputs,
+			"`#[native]` functions can take at most 255 arguments",
+		));
+	}
+
+	let name = &sig.ident;
+	let lox_name = name.unraw().to_string();
+	let argc = types.len();
+	let receivers = is_method as usize;
+	let arity = argc - receivers;
+	let arity_u8 = arity as u8;
+	let vars: Vec<_> = (0..argc)
+		.map(|idx| format_ident!("__arg{}", idx))
+		.collect();
+	let indices = 0..argc;
+	let labels = (0..argc).map(|idx| match (is_method, idx) {
+		(true, 0) => "receiver".to_string(),
+		(true, _) => format!("argument {}", idx),
+		(false, _) => format!("argument {}", idx + 1),
+	});
+	let vm_arg = if takes_vm { Some(quote!(__vm,)) } else { None };
+
+	let fallible = match &sig.output {
+		ReturnType::Type(_, ty) => match ty.as_ref() {
+			Type::Path(path) => path
+				.path
+				.segments
+				.last()
+				.map(|segment| segment.ident == "Result")
+				.unwrap_or(false),
+			_ => false,
+		},
+		ReturnType::Default => false,
+	};
+	let unwrap = if fallible { Some(quote!(?)) } else { None };
+
+	let (docs, attrs): (Vec<_>, Vec<_>) = ast
+		.attrs
+		.iter()
+		.partition(|attr| attr.path.is_ident("doc"));
+	let vis = &ast.vis;
+	let block = &ast.block;
+
+	Ok(quote! {
+		#(#docs)*
+		#[allow(non_upper_case_globals)]
+		#vis const #name: ::vm::Native = {
+			#(#attrs)*
+			#sig #block
+
+			fn __native(
+				__vm: &mut ::vm::VM,
+				__args: &[::vm::Value],
+			) -> ::std::result::Result<::vm::Value, ::vm::Error> {
+				if __args.len() != #argc {
+					return Err(::vm::Error::runtime(format!(
+						"Expected {} arguments but got {}.",
+						#arity,
+						__args.len().saturating_sub(#receivers),
+					)));
+				}
+				#(
+					let #vars: #types = ::vm::FromLox::from_lox(__args[#indices], __vm)
+						.map_err(|err| ::vm::Error::runtime(format!(
+							"Invalid {} to `{}`: {}",
+							#labels,
+							#lox_name,
+							err,
+						)))?;
+				)*
+
+				let result = #name(#vm_arg #(#vars),*)#unwrap;
+
+				Ok(::vm::IntoLox::into_lox(result, __vm))
+			}
+
+			::vm::Native {
+				name: #lox_name,
+				arity: #arity_u8,
+				function: __native,
+			}
+		};
+	})
+}
+
+/// Whether `ty` names the `VM` type, however it's imported.
+fn is_vm(ty: &Type) -> bool {
+	match ty {
+		Type::Path(path) if path.qself.is_none() => path
+			.path
+			.segments
+			.last()
+			.map(|segment| segment.ident == "VM" && segment.arguments.is_empty())
+			.unwrap_or(false),
+		_ => false,
+	}
+}
+
+/// Converts a struct with named fields into an instance of the VM's host class with
+/// the struct's name, with a field for each of the struct's fields.
+#[proc_macro_derive(IntoLox)]
+pub fn derive_into_lox(input: pm::TokenStream) -> pm::TokenStream {
+	let ast = parse_macro_input!(input as DeriveInput);
+	let fields = match named_fields(&ast, "IntoLox") {
+		Ok(fields) => fields,
+		Err(err) => return err.to_compile_error().into(),
+	};
+
+	let name = &ast.ident;
+	let class = name.unraw().to_string();
+	let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+	let keys = fields
+		.iter()
+		.map(|field| field.unraw().to_string());
+
+	let result = quote! {
+		impl #impl_generics ::vm::IntoLox for #name #ty_generics #where_clause {
+			fn into_lox(self, vm: &mut ::vm::VM) -> ::vm::Value {
+				let instance = vm.host_instance(#class);
+				#(
+					let value = ::vm::IntoLox::into_lox(self.#fields, vm);
+					vm.set_field(instance, #keys, value);
+				)*
+
+				::vm::Value::Obj(instance)
+			}
+		}
+	};
+
+	result.into()
+}
+
+/// Converts an instance into a struct with named fields, by converting the instance's
+/// field with the same name for each of them. The instance can be of any class.
+#[proc_macro_derive(FromLox)]
+pub fn derive_from_lox(input: pm::TokenStream) -> pm::TokenStream {
+	let ast = parse_macro_input!(input as DeriveInput);
+	let fields = match named_fields(&ast, "FromLox") {
+		Ok(fields) => fields,
+		Err(err) => return err.to_compile_error().into(),
+	};
+
+	let name = &ast.ident;
+	let expected = format!("a `{}` instance", name.unraw());
+	let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+	let keys = fields
+		.iter()
+		.map(|field| field.unraw().to_string());
+
+	let result = quote! {
+		impl #impl_generics ::vm::FromLox for #name #ty_generics #where_clause {
+			fn from_lox(
+				value: ::vm::Value,
+				vm: &::vm::VM,
+			) -> ::std::result::Result<Self, ::vm::ConversionError> {
+				if !vm.is_instance(value) {
+					return Err(::vm::ConversionError::expected(#expected, value, vm));
+				}
+
+				Ok(Self {
+					#(
+						#fields: {
+							let field = vm
+								.field(value, #keys)
+								.ok_or(::vm::ConversionError::MissingField(#keys))?;
+
+							::vm::FromLox::from_lox(field, vm)
+								.map_err(|err| err.in_field(#keys))?
+						},
+					)*
+				})
+			}
+		}
+	};
+
+	result.into()
+}
+
+fn named_fields(ast: &DeriveInput, derive: &str) -> syn::Result<Vec<pm2::Ident>> {
+	match &ast.data {
+		Data::Struct(data) => match &data.fields {
+			Fields::Named(fields) => Ok(fields
+				.named
+				.iter()
+				.map(|field| field.ident.clone().unwrap())
+				.collect()),
+			_ => Err(syn::Error::new_spanned(
+				&data.fields,
+				format!(
+					"`{}` can only be derived for structs with named fields",
+					derive
+				),
+			)),
+		},
+		_ => Err(syn::Error::new_spanned(
+			&ast.ident,
+			format!(
+				"`{}` can only be derived for structs with named fields",
+				derive
+			),
+		)),
+	}
+}
@@ -0,0 +1,7 @@
+#[macro_use]
+extern crate macro_utils;
+
+fn main() {}
+
+#[native]
+async fn wait() {}
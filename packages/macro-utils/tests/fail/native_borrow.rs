@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate macro_utils;
+
+fn main() {}
+
+#[native]
+fn push(buf: &mut Vec<f64>, x: f64) {
+	buf.push(x);
+}
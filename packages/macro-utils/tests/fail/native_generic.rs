@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate macro_utils;
+
+fn main() {}
+
+#[native]
+fn identity<T>(value: T) -> T {
+	value
+}
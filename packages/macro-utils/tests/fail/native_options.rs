@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate macro_utils;
+
+fn main() {}
+
+#[native(static)]
+fn answer() -> f64 {
+	42.
+}
@@ -0,0 +1,109 @@
+#![allow(dead_code)]
+
+use vm::{native, ConversionError, Error, FromLox, Userdata, Value, VM};
+
+fn main() {
+	let mut vm = VM::new();
+	vm.register_native(&add);
+	vm.register_native(&greet);
+	vm.register_native(&divide);
+	vm.register_native(&r#loop);
+	vm.register_native(&byte);
+	vm.define_class("Greeter", &[hail]);
+
+	vm.interpret(
+		r#"
+fun sum() { return add(2, 3); }
+fun greeting() { return greet("world"); }
+fun div(a, b) { return divide(a, b); }
+fun bad_arg() { return add(1, "two"); }
+fun hail_world(greeter) { return greeter.hail("world"); }
+fun to_byte(n) { return byte(n); }
+"#
+		.into(),
+	)
+	.unwrap();
+
+	assert_eq!(call(&mut vm, "sum", &[]), Ok(Value::Number(5.)));
+
+	let greeting = call(&mut vm, "greeting", &[]).unwrap();
+	assert_eq!(String::from_lox(greeting, &vm).unwrap(), "Hello, world!");
+
+	let (six, two, zero) = (Value::Number(6.), Value::Number(2.), Value::Number(0.));
+	assert_eq!(call(&mut vm, "div", &[six, two]), Ok(Value::Number(3.)));
+	assert_eq!(
+		call(&mut vm, "div", &[six, zero]),
+		Err("Can't divide 6 by zero".to_string())
+	);
+	assert_eq!(
+		call(&mut vm, "bad_arg", &[]),
+		Err("Invalid argument 2 to `add`: Expected a number, found `two`".to_string())
+	);
+
+	let n = Value::Number(255.);
+	assert_eq!(call(&mut vm, "to_byte", &[n]), Ok(n));
+	assert_eq!(
+		call(&mut vm, "to_byte", &[Value::Number(256.)]),
+		Err("Expected a valid `u8`, found `256`".to_string())
+	);
+
+	let greeter = Greeter {
+		greeting: "Hail".into(),
+	};
+	let greeter = vm.host_userdata("Greeter", greeter);
+	let hailed = call(&mut vm, "hail_world", &[greeter.into()]).unwrap();
+	assert_eq!(String::from_lox(hailed, &vm).unwrap(), "Hail, world!");
+	assert_eq!(hail.arity, 1);
+
+	assert_eq!(r#loop.name, "loop");
+	assert_eq!(r#loop.arity, 0);
+}
+
+fn call(vm: &mut VM, name: &str, args: &[Value]) -> Result<Value, String> {
+	let function = vm.global(name).unwrap();
+	vm.call(function, args).map_err(|err| match err {
+		Error::Runtime(err) => err.message,
+		other => other.to_string(),
+	})
+}
+
+/// Adds two numbers
+#[native]
+fn add(a: f64, b: f64) -> f64 {
+	a + b
+}
+
+#[native]
+fn greet(vm: &mut VM, name: String) -> Value {
+	Value::Obj(vm.intern(format!("Hello, {}!", name)))
+}
+
+#[native]
+fn divide(a: u32, b: u32) -> Result<u32, Error> {
+	a.checked_div(b)
+		.ok_or_else(|| Error::runtime(format!("Can't divide {} by zero", a)))
+}
+
+#[native]
+pub fn r#loop() {}
+
+#[native]
+fn byte(vm: &mut VM, n: Value) -> Result<u8, ConversionError> {
+	let n = u8::from_lox(n, vm)?;
+	Ok(n)
+}
+
+struct Greeter {
+	greeting: String,
+}
+
+impl Userdata for Greeter {}
+
+#[native(method)]
+fn hail(vm: &mut VM, this: Value, name: String) -> Result<String, Error> {
+	let greeter = vm
+		.userdata::<Greeter>(this)
+		.ok_or_else(|| Error::runtime("Expected a `Greeter`"))?;
+
+	Ok(format!("{}, {}!", greeter.greeting, name))
+}
@@ -4,4 +4,6 @@ use trybuild::TestCases;
 fn tests() {
 	let t = TestCases::new();
 	t.pass("tests/trace.rs");
+	t.pass("tests/native.rs");
+	t.compile_fail("tests/fail/*.rs");
 }
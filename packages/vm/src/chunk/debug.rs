@@ -7,10 +7,10 @@ use super::{Chunk, OpCode};
 impl fmt::Debug for Chunk {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		let mut bytes = self.iter().enumerate();
-		let lines = &self.lines;
+		let source_map = &self.source_map;
 		let constants = &self.constants;
 
-		f.debug_chunk(&mut bytes, lines, constants)
+		f.debug_chunk(&mut bytes, source_map, constants)
 	}
 }
 
@@ -24,6 +24,7 @@ impl fmt::Debug for OpCode {
 			Self::True       => "TRUE",
 			Self::False      => "FALSE",
 			Self::Nil        => "NIL",
+			Self::Pop        => "POP",
 			Self::Add        => "ADD",
 			Self::Subtract   => "SUBTRACT",
 			Self::Multiply   => "MULTIPLY",
@@ -33,6 +34,55 @@ impl fmt::Debug for OpCode {
 			Self::Equal      => "EQUAL",
 			Self::Greater    => "GREATER",
 			Self::Less       => "LESS",
+			Self::Print      => "PRINT",
+
+			Self::DefineGlobal   => "DEFINE_GLOBAL",
+			Self::DefineGlobal16 => "DEFINE_GLOBAL_16",
+			Self::DefineGlobal24 => "DEFINE_GLOBAL_24",
+			Self::GetGlobal      => "GET_GLOBAL",
+			Self::GetGlobal16    => "GET_GLOBAL_16",
+			Self::GetGlobal24    => "GET_GLOBAL_24",
+			Self::SetGlobal      => "SET_GLOBAL",
+			Self::SetGlobal16    => "SET_GLOBAL_16",
+			Self::SetGlobal24    => "SET_GLOBAL_24",
+			Self::GetLocal       => "GET_LOCAL",
+			Self::SetLocal       => "SET_LOCAL",
+			Self::GetUpvalue     => "GET_UPVALUE",
+			Self::SetUpvalue     => "SET_UPVALUE",
+			Self::CloseUpvalue   => "CLOSE_UPVALUE",
+
+			Self::Jump           => "JUMP",
+			Self::JumpIfFalse    => "JUMP_IF_FALSE",
+			Self::Loop           => "LOOP",
+
+			Self::Call           => "CALL",
+			Self::Closure        => "CLOSURE",
+			Self::Closure16      => "CLOSURE_16",
+			Self::Closure24      => "CLOSURE_24",
+
+			Self::Class          => "CLASS",
+			Self::Class16        => "CLASS_16",
+			Self::Class24        => "CLASS_24",
+			Self::GetProperty    => "GET_PROPERTY",
+			Self::GetProperty16  => "GET_PROPERTY_16",
+			Self::GetProperty24  => "GET_PROPERTY_24",
+			Self::SetProperty    => "SET_PROPERTY",
+			Self::SetProperty16  => "SET_PROPERTY_16",
+			Self::SetProperty24  => "SET_PROPERTY_24",
+			Self::Method         => "METHOD",
+			Self::Method16       => "METHOD_16",
+			Self::Method24       => "METHOD_24",
+			Self::Invoke         => "INVOKE",
+			Self::Invoke16       => "INVOKE_16",
+			Self::Invoke24       => "INVOKE_24",
+			Self::Inherit        => "INHERIT",
+			Self::GetSuper       => "GET_SUPER",
+			Self::GetSuper16     => "GET_SUPER_16",
+			Self::GetSuper24     => "GET_SUPER_24",
+			Self::SuperInvoke    => "SUPER_INVOKE",
+			Self::SuperInvoke16  => "SUPER_INVOKE_16",
+			Self::SuperInvoke24  => "SUPER_INVOKE_24",
+
 			Self::Return     => "RETURN",
 		};
 		debug::print_aligned(f, name)
@@ -111,4 +111,4 @@ macro_rules! impl_join_bytes {
 impl_join_bytes!(by ref <'a> : Iter<'a, u8>);
 impl_join_bytes!(by value : IntoIter<u8>);
 impl_join_bytes!(enumerated ref <'a> : Enumerate<Iter<'a, u8>>);
-impl_join_bytes!(enumerated value : super::Consumable);
+impl_join_bytes!(enumerated value : crate::vm::CallFrame);
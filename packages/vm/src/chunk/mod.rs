@@ -1,24 +1,26 @@
 use std::{
 	convert::TryFrom,
+	mem,
 	ops::{Deref, DerefMut},
+	rc::Rc,
 };
 
+use gramatika::Span;
 use num_derive::FromPrimitive;
 
 mod debug;
-mod into_iter;
 mod join_bytes;
-mod lines;
+mod source_map;
 
 #[cfg(test)]
 mod tests;
 
 use crate::{
-	repr::Value,
+	repr::{Trace, Tracer, Value},
 	vector::{vector, Vector},
 };
 
-pub use self::{into_iter::Consumable, join_bytes::JoinBytes, lines::Lines};
+pub use self::{join_bytes::JoinBytes, source_map::SourceMap};
 
 #[derive(FromPrimitive, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
@@ -30,6 +32,7 @@ pub enum OpCode {
 	Nil        = 0x03,
 	True       = 0x04,
 	False      = 0x05,
+	Pop        = 0x06,
 	Add        = 0x10,
 	Subtract   = 0x11,
 	Multiply   = 0x12,
@@ -39,11 +42,109 @@ pub enum OpCode {
 	Equal      = 0x16,
 	Greater    = 0x17,
 	Less       = 0x18,
+	Print      = 0x20,
+
+	DefineGlobal   = 0x30,
+	DefineGlobal16 = 0x31,
+	DefineGlobal24 = 0x32,
+	GetGlobal      = 0x33,
+	GetGlobal16    = 0x34,
+	GetGlobal24    = 0x35,
+	SetGlobal      = 0x36,
+	SetGlobal16    = 0x37,
+	SetGlobal24    = 0x38,
+	GetLocal       = 0x39,
+	SetLocal       = 0x3A,
+	GetUpvalue     = 0x3B,
+	SetUpvalue     = 0x3C,
+	CloseUpvalue   = 0x3D,
+
+	Jump           = 0x40,
+	JumpIfFalse    = 0x41,
+	Loop           = 0x42,
+
+	Call           = 0x50,
+	Closure        = 0x51,
+	Closure16      = 0x52,
+	Closure24      = 0x53,
+
+	Class          = 0x60,
+	Class16        = 0x61,
+	Class24        = 0x62,
+	GetProperty    = 0x63,
+	GetProperty16  = 0x64,
+	GetProperty24  = 0x65,
+	SetProperty    = 0x66,
+	SetProperty16  = 0x67,
+	SetProperty24  = 0x68,
+	Method         = 0x69,
+	Method16       = 0x6A,
+	Method24       = 0x6B,
+	Invoke         = 0x6C,
+	Invoke16       = 0x6D,
+	Invoke24       = 0x6E,
+	Inherit        = 0x70,
+	GetSuper       = 0x71,
+	GetSuper16     = 0x72,
+	GetSuper24     = 0x73,
+	SuperInvoke    = 0x74,
+	SuperInvoke16  = 0x75,
+	SuperInvoke24  = 0x76,
+
 	Return     = 0xFF,
 }
 
+impl OpCode {
+	/// For instructions whose operand is an index into the constant pool, returns the
+	/// width of that operand in bytes.
+	pub fn const_width(self) -> Option<usize> {
+		use OpCode::*;
+
+		match self {
+			Constant | DefineGlobal | GetGlobal | SetGlobal | Closure | Class
+			| GetProperty | SetProperty | Method | Invoke | GetSuper | SuperInvoke => Some(1),
+			Constant16 | DefineGlobal16 | GetGlobal16 | SetGlobal16 | Closure16
+			| Class16 | GetProperty16 | SetProperty16 | Method16 | Invoke16
+			| GetSuper16 | SuperInvoke16 => Some(2),
+			Constant24 | DefineGlobal24 | GetGlobal24 | SetGlobal24 | Closure24
+			| Class24 | GetProperty24 | SetProperty24 | Method24 | Invoke24
+			| GetSuper24 | SuperInvoke24 => Some(3),
+			_ => None,
+		}
+	}
+
+	/// For instructions with an operand that _isn't_ a constant pool index (e.g. a
+	/// stack slot), returns the width of that operand in bytes. For instructions that
+	/// have both (i.e. `Invoke` and `SuperInvoke`), this operand follows the
+	/// constant pool index.
+	pub fn operand_width(self) -> Option<usize> {
+		use OpCode::*;
+
+		match self {
+			GetLocal | SetLocal | GetUpvalue | SetUpvalue | Call | Invoke | Invoke16
+			| Invoke24 | SuperInvoke | SuperInvoke16 | SuperInvoke24 => Some(1),
+			Jump | JumpIfFalse | Loop => Some(2),
+			_ => None,
+		}
+	}
+
+	/// For jump instructions, returns the absolute offset of the jump's destination,
+	/// given the offset immediately following the instruction and its operand.
+	pub fn jump_target(self, next: usize, distance: usize) -> Option<usize> {
+		use OpCode::*;
+
+		match self {
+			Jump | JumpIfFalse => Some(next + distance),
+			Loop => Some(next - distance),
+			_ => None,
+		}
+	}
+}
+
 pub struct OpCodeError(pub String);
 
+pub struct JumpError(pub String);
+
 impl TryFrom<u8> for OpCode {
 	type Error = OpCodeError;
 
@@ -55,6 +156,7 @@ impl TryFrom<u8> for OpCode {
 			0x03 => Ok(OpCode::Nil),
 			0x04 => Ok(OpCode::True),
 			0x05 => Ok(OpCode::False),
+			0x06 => Ok(OpCode::Pop),
 			0x10 => Ok(OpCode::Add),
 			0x11 => Ok(OpCode::Subtract),
 			0x12 => Ok(OpCode::Multiply),
@@ -64,6 +166,50 @@ impl TryFrom<u8> for OpCode {
 			0x16 => Ok(OpCode::Equal),
 			0x17 => Ok(OpCode::Greater),
 			0x18 => Ok(OpCode::Less),
+			0x20 => Ok(OpCode::Print),
+			0x30 => Ok(OpCode::DefineGlobal),
+			0x31 => Ok(OpCode::DefineGlobal16),
+			0x32 => Ok(OpCode::DefineGlobal24),
+			0x33 => Ok(OpCode::GetGlobal),
+			0x34 => Ok(OpCode::GetGlobal16),
+			0x35 => Ok(OpCode::GetGlobal24),
+			0x36 => Ok(OpCode::SetGlobal),
+			0x37 => Ok(OpCode::SetGlobal16),
+			0x38 => Ok(OpCode::SetGlobal24),
+			0x39 => Ok(OpCode::GetLocal),
+			0x3A => Ok(OpCode::SetLocal),
+			0x3B => Ok(OpCode::GetUpvalue),
+			0x3C => Ok(OpCode::SetUpvalue),
+			0x3D => Ok(OpCode::CloseUpvalue),
+			0x40 => Ok(OpCode::Jump),
+			0x41 => Ok(OpCode::JumpIfFalse),
+			0x42 => Ok(OpCode::Loop),
+			0x50 => Ok(OpCode::Call),
+			0x51 => Ok(OpCode::Closure),
+			0x52 => Ok(OpCode::Closure16),
+			0x53 => Ok(OpCode::Closure24),
+			0x60 => Ok(OpCode::Class),
+			0x61 => Ok(OpCode::Class16),
+			0x62 => Ok(OpCode::Class24),
+			0x63 => Ok(OpCode::GetProperty),
+			0x64 => Ok(OpCode::GetProperty16),
+			0x65 => Ok(OpCode::GetProperty24),
+			0x66 => Ok(OpCode::SetProperty),
+			0x67 => Ok(OpCode::SetProperty16),
+			0x68 => Ok(OpCode::SetProperty24),
+			0x69 => Ok(OpCode::Method),
+			0x6A => Ok(OpCode::Method16),
+			0x6B => Ok(OpCode::Method24),
+			0x6C => Ok(OpCode::Invoke),
+			0x6D => Ok(OpCode::Invoke16),
+			0x6E => Ok(OpCode::Invoke24),
+			0x70 => Ok(OpCode::Inherit),
+			0x71 => Ok(OpCode::GetSuper),
+			0x72 => Ok(OpCode::GetSuper16),
+			0x73 => Ok(OpCode::GetSuper24),
+			0x74 => Ok(OpCode::SuperInvoke),
+			0x75 => Ok(OpCode::SuperInvoke16),
+			0x76 => Ok(OpCode::SuperInvoke24),
 			0xFF => Ok(OpCode::Return),
 			_ => Err(OpCodeError(format!("UNKNOWN: {:#04x}", byte))),
 		}
@@ -71,68 +217,137 @@ impl TryFrom<u8> for OpCode {
 }
 
 pub struct Chunk {
-	source: String,
+	/// The source code of the whole script, shared by the chunks of every function
+	/// compiled from it
+	source: Rc<str>,
 	data: Vector<u8>,
 	constants: Vector<Value>,
-	lines: Lines,
+	source_map: SourceMap,
 }
 
 impl Chunk {
 	pub fn new() -> Self {
 		Self {
-			source: String::new(),
+			source: "".into(),
 			data: vector![],
 			constants: vector![],
-			lines: Lines::new(),
+			source_map: SourceMap::new(),
 		}
 	}
 
-	pub fn write_instr(&mut self, op: OpCode, line: usize) {
-		self.write(op as u8, line);
+	pub fn write_instr(&mut self, op: OpCode, span: Span) {
+		self.write(op as u8, span);
 	}
 
-	pub fn write_const(&mut self, value: Value, line: usize) {
+	pub fn write_const(&mut self, value: Value, span: Span) {
 		let handle = self.add_constant(value);
+		self.write_indexed(OpCode::Constant, handle, span);
+	}
 
+	/// Writes an instruction whose operand is an index into the constant pool, using
+	/// the 8-, 16- or 24-bit form of `op` depending on the size of the index. The
+	/// wider forms of `op` must immediately follow it in the `OpCode` enum.
+	pub fn write_indexed(&mut self, op: OpCode, handle: usize, span: Span) {
 		match handle {
 			0..=255 => {
-				self.write(OpCode::Constant as u8, line);
-				self.write(handle as u8, line);
+				self.write(op as u8, span);
+				self.write(handle as u8, span);
 			}
 			256..=65_535 => {
-				self.write(OpCode::Constant16 as u8, line);
+				self.write(op as u8 + 1, span);
 				let bytes = (handle as u16).to_be_bytes();
-				self.extend(&bytes, line);
+				self.extend(&bytes, span);
 			}
 			_ => {
-				self.write(OpCode::Constant24 as u8, line);
+				self.write(op as u8 + 2, span);
 				let [_, b, c, d] = (handle as u32).to_be_bytes();
-				self.extend(&[b, c, d], line);
+				self.extend(&[b, c, d], span);
 			}
 		}
 	}
 
-	pub fn set_source(&mut self, src: String) {
+	/// Writes a forward jump instruction with a placeholder operand, returning the
+	/// offset of the operand so it can be filled in later with `patch_jump`.
+	pub fn write_jump(&mut self, op: OpCode, span: Span) -> usize {
+		self.write(op as u8, span);
+		self.extend(&[0xff, 0xff], span);
+
+		self.data.len() - 2
+	}
+
+	/// Back-patches the operand of the jump at `offset` to land on the next
+	/// instruction to be written.
+	pub fn patch_jump(&mut self, offset: usize) -> Result<(), JumpError> {
+		// -2 to adjust for the jump operand itself
+		let distance = self.data.len() - offset - 2;
+		if distance > u16::MAX as usize {
+			return Err(JumpError("Too much code to jump over.".into()));
+		}
+
+		let [a, b] = (distance as u16).to_be_bytes();
+		self.data[offset] = a;
+		self.data[offset + 1] = b;
+
+		Ok(())
+	}
+
+	/// Writes a `Loop` instruction jumping backward to `loop_start`.
+	pub fn write_loop(&mut self, loop_start: usize, span: Span) -> Result<(), JumpError> {
+		self.write(OpCode::Loop as u8, span);
+
+		// +2 to account for the operand we're about to write
+		let distance = self.data.len() - loop_start + 2;
+		if distance > u16::MAX as usize {
+			return Err(JumpError("Loop body too large.".into()));
+		}
+
+		self.extend(&(distance as u16).to_be_bytes(), span);
+
+		Ok(())
+	}
+
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+
+	pub fn set_source(&mut self, src: Rc<str>) {
 		self.source = src;
 	}
 
-	fn write(&mut self, byte: u8, line: usize) {
+	fn write(&mut self, byte: u8, span: Span) {
 		self.data.push(byte);
-		self.lines.add_byte(line, self.data.len() - 1);
+		self.source_map
+			.add_byte(span, self.data.len() - 1);
 	}
 
-	pub fn extend(&mut self, bytes: &[u8], line: usize) {
-		self.lines.add_byte(line, self.data.len());
+	pub fn extend(&mut self, bytes: &[u8], span: Span) {
+		self.source_map.add_byte(span, self.data.len());
 		// TODO: Add a proper Vector::extend implementation
 		for byte in bytes.iter() {
 			self.data.push(*byte);
 		}
 	}
 
-	fn add_constant(&mut self, value: Value) -> usize {
+	pub fn add_constant(&mut self, value: Value) -> usize {
 		self.constants.push(value);
 		self.constants.len() - 1
 	}
+
+	pub fn read_const(&self, handle: usize) -> Option<Value> {
+		self.constants.get(handle).copied()
+	}
+
+	pub fn source_map(&self) -> &SourceMap {
+		&self.source_map
+	}
+
+	/// The number of bytes allocated for the chunk's code, constants and source map.
+	/// The source code itself is shared with other chunks, so it isn't counted.
+	pub fn size(&self) -> usize {
+		self.data.capacity()
+			+ self.constants.capacity() * mem::size_of::<Value>()
+			+ self.source_map.size()
+	}
 }
 
 impl Deref for Chunk {
@@ -148,3 +363,9 @@ impl DerefMut for Chunk {
 		&mut *self.data
 	}
 }
+
+impl Trace for Chunk {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.constants.trace(tracer);
+	}
+}
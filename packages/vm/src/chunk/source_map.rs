@@ -0,0 +1,80 @@
+use std::mem;
+
+use gramatika::Span;
+
+use crate::vector::{vector, Vector};
+
+#[derive(Clone, Copy)]
+struct SpanStart {
+	span: Span,
+	offset: usize,
+}
+
+/// Maps each byte of a chunk back to the span of source code it was compiled from.
+/// Only the first byte of each run of bytes with the same span is recorded, so an
+/// instruction's operands (and consecutive instructions for the same token) don't
+/// take up any extra space.
+pub struct SourceMap {
+	inner: Vector<SpanStart>,
+}
+
+impl SourceMap {
+	pub fn new() -> Self {
+		Self { inner: vector![] }
+	}
+
+	pub fn add_byte(&mut self, span: Span, offset: usize) {
+		let tail = self.tail();
+		if tail.is_none() || tail.unwrap().span != span {
+			self.inner.push(SpanStart { span, offset });
+		}
+	}
+
+	pub fn find_span(&self, offset: usize) -> Span {
+		// Binary search for the last SpanStart whose offset <= the given param
+		let mut start = 0;
+		let mut end = self.last_idx();
+
+		loop {
+			let mid = (start + end) / 2;
+			let span_start = &self.inner[mid];
+
+			if offset < span_start.offset {
+				// Needle is in first half of the haystack
+				end = mid - 1;
+			} else if mid == self.last_idx() || offset < self.inner[mid + 1].offset {
+				// Found it
+				break span_start.span;
+			} else {
+				// Needle is in second half of the haystack
+				start = mid + 1;
+			}
+		}
+	}
+
+	/// The number of bytes allocated for the map's entries
+	pub fn size(&self) -> usize {
+		self.inner.capacity() * mem::size_of::<SpanStart>()
+	}
+
+	/// The (1-based) line number where the span for `offset` starts
+	pub fn find_line(&self, offset: usize) -> usize {
+		self.find_span(offset).start.line + 1
+	}
+
+	fn last_idx(&self) -> usize {
+		if self.inner.is_empty() {
+			0
+		} else {
+			self.inner.len() - 1
+		}
+	}
+
+	fn tail(&self) -> Option<&SpanStart> {
+		if self.inner.is_empty() {
+			None
+		} else {
+			Some(&self.inner[self.last_idx()])
+		}
+	}
+}
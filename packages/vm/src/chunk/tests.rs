@@ -1,14 +1,26 @@
+use gramatika::Position;
+
 use super::*;
 
+/// An empty span at the start of the given (1-based) line
+fn on_line(line: usize) -> Span {
+	let start = Position {
+		line: line - 1,
+		character: 0,
+	};
+
+	Span { start, end: start }
+}
+
 #[test]
 fn it_works() {
 	let mut chunk = Chunk::new();
-	chunk.write_const(1.2.into(), 123);
-	chunk.write_instr(OpCode::Return, 123);
+	chunk.write_const(1.2.into(), on_line(123));
+	chunk.write_instr(OpCode::Return, on_line(123));
 
-	chunk.write_const(420.0.into(), 124);
-	chunk.write_const(69.0.into(), 124);
-	chunk.write_instr(OpCode::Return, 124);
+	chunk.write_const(420.0.into(), on_line(124));
+	chunk.write_const(69.0.into(), on_line(124));
+	chunk.write_instr(OpCode::Return, on_line(124));
 
 	// eprintln!("{:?}", chunk);
 	let expected = r#"
@@ -30,7 +42,7 @@ fn it_can_store_more_than_255_constants() {
 		if i > 0 && i % 3 == 0 {
 			line += 1;
 		}
-		chunk.write_const((i as f64).into(), line);
+		chunk.write_const((i as f64).into(), on_line(line));
 	}
 
 	// eprintln!("{:?}", chunk);
@@ -47,10 +59,47 @@ fn it_can_store_more_than_65535_constants() {
 		if i > 0 && i % 100 == 0 {
 			line += 1;
 		}
-		chunk.write_const((i as f64).into(), line);
+		chunk.write_const((i as f64).into(), on_line(line));
 	}
 
 	// eprintln!("{:?}", chunk);
 	assert_eq!(chunk.constants.len(), 65_546);
 	assert_eq!(chunk.constants[65_545], Value::Number(65_545.));
 }
+
+#[test]
+fn it_patches_jumps() {
+	let mut chunk = Chunk::new();
+	chunk.write_instr(OpCode::True, on_line(1));
+	let jump = chunk.write_jump(OpCode::JumpIfFalse, on_line(1));
+	chunk.write_instr(OpCode::Pop, on_line(2));
+	chunk.write_const(1.0.into(), on_line(2));
+	chunk.write_instr(OpCode::Print, on_line(2));
+	assert!(chunk.patch_jump(jump).is_ok());
+	assert!(chunk.write_loop(0, on_line(3)).is_ok());
+	chunk.write_instr(OpCode::Return, on_line(3));
+
+	// eprintln!("{:?}", chunk);
+	let expected = r#"
+0000     1 TRUE
+0001     | JUMP_IF_FALSE     0001 -> 0008
+0004     2 POP
+0005     | CONSTANT          [0] '1'
+0007     | PRINT
+0008     3 LOOP              0008 -> 0000
+0011     | RETURN
+"#;
+	assert_eq!(&format!("\n{:?}\n", chunk), expected);
+}
+
+#[test]
+fn it_rejects_jumps_that_are_too_long() {
+	let mut chunk = Chunk::new();
+	let jump = chunk.write_jump(OpCode::Jump, on_line(1));
+	for _ in 0..=(u16::MAX as usize) {
+		chunk.write_instr(OpCode::Nil, on_line(1));
+	}
+
+	assert!(chunk.patch_jump(jump).is_err());
+	assert!(chunk.write_loop(0, on_line(1)).is_err());
+}
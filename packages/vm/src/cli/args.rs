@@ -1,5 +1,9 @@
 use core::fmt;
-use std::{env, fs};
+use std::{
+	env, fs,
+	io::{self, Read},
+	path::PathBuf,
+};
 
 use bitflags::bitflags;
 use gramatika::{Parse, ParseStreamer, Span, Spanned, SpannedError, Token as _};
@@ -12,51 +16,116 @@ lazy_static! {
 
 #[derive(Debug)]
 pub struct Args {
-	pub example: Option<String>,
+	/// The script to run, or `None` to start the REPL
+	pub script: Option<Script>,
+	/// Any arguments after the script's path, which are passed on to the script
+	pub script_args: Vec<String>,
+	/// Whether to use the line-oriented REPL, even in a terminal
+	pub plain: bool,
 	pub debug: DebugFlags,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum Script {
+	File(PathBuf),
+	/// Given as `-`
+	Stdin,
+}
+
 bitflags! {
 	pub struct DebugFlags: u8 {
-		const NONE    = 0b000;
-		const PARSE   = 0b001;
-		const CODEGEN = 0b010;
-		const EXEC    = 0b100;
+		const NONE      = 0b0000;
+		const PARSE     = 0b0001;
+		const CODEGEN   = 0b0010;
+		const EXEC      = 0b0100;
+		/// Not a logging flag: collects garbage before every allocation
+		const STRESS_GC = 0b1000;
 
 		const COMPILE = Self::PARSE.bits | Self::CODEGEN.bits;
 		const ALL = Self::COMPILE.bits | Self::EXEC.bits;
 	}
 }
 
+/// Parses the process's arguments, which take the form
+/// `[--option=value...] [script [script args...]]`.
 pub fn args() -> anyhow::Result<Args> {
-	let raw = env::args().into_iter().skip(1).join("\n");
-	let (_src, mut args) = self::parse(raw)?;
+	let args = self::from_iter(env::args().skip(1))?;
 
 	let mut flags = DEBUG_FLAGS.lock();
 	*flags = args.debug;
 	drop(flags);
 
-	if let Some(example) = args.example.as_mut() {
-		let mut path = env::current_dir()?;
-		path.extend(["packages", "spec", "src", "examples"]);
-		path.push(format!("{}.lox", example));
+	Ok(args)
+}
 
-		*example = fs::read_to_string(path)?;
+pub(super) fn from_iter<I>(raw: I) -> anyhow::Result<Args>
+where I: IntoIterator<Item = String> {
+	// Only the options are tokenized -- the script's path and arguments are taken
+	// as-is, whatever characters they contain
+	let mut raw = raw.into_iter().peekable();
+	let options = raw
+		.peeking_take_while(|arg| arg.starts_with("--"))
+		.join("\n");
+	let mut args = self::parse(options)?;
+
+	if let Some(path) = raw.next() {
+		if args.script.is_some() {
+			anyhow::bail!("Can't run both an example and `{}`", path);
+		}
+		args.script = Some(match path.as_str() {
+			"-" => Script::Stdin,
+			_ => Script::File(path.into()),
+		});
 	}
+	args.script_args = raw.collect();
 
 	Ok(args)
 }
 
+impl Script {
+	/// The script from `packages/spec/src/examples` with the given name
+	fn example(name: &str) -> Self {
+		let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+		path.extend(["..", "spec", "src", "examples"]);
+		path.push(format!("{}.lox", name));
+
+		Script::File(path)
+	}
+
+	pub fn read(&self) -> io::Result<String> {
+		match self {
+			Script::File(path) => fs::read_to_string(path),
+			Script::Stdin => {
+				let mut src = String::new();
+				io::stdin().read_to_string(&mut src)?;
+
+				Ok(src)
+			}
+		}
+	}
+}
+
+impl fmt::Display for Script {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Script::File(path) => path.display().fmt(f),
+			Script::Stdin => write!(f, "<stdin>"),
+		}
+	}
+}
+
 pub fn debug_flags<'a>() -> MutexGuard<'a, DebugFlags> {
 	DEBUG_FLAGS.lock()
 }
 
-fn parse(raw_args: String) -> gramatika::Result<'static, (String, Args)> {
+// The error is formatted here because it borrows the parser's source, which doesn't
+// outlive the parser
+fn parse(raw_args: String) -> anyhow::Result<Args> {
 	let mut parser = ParseStream::from(raw_args);
-	let args = parser.parse::<Args>()?;
-	let (src, _) = parser.into_inner();
 
-	Ok((src, args))
+	parser
+		.parse::<Args>()
+		.map_err(|err| anyhow::anyhow!("{}", err))
 }
 
 type ParseStream<'a> = gramatika::ParseStream<'a, Token<'a>, Lexer<'a>>;
@@ -75,6 +144,9 @@ pub enum Token<'a> {
 	#[pattern = "="]
 	Equal(&'a str, Span),
 
+	#[pattern = ","]
+	Comma(&'a str, Span),
+
 	#[pattern = "[_a-zA-Z][-_a-zA-Z0-9]+"]
 	Word(&'a str, Span),
 }
@@ -86,7 +158,9 @@ impl<'a> Parse<'a> for Args {
 		use TokenKind::*;
 
 		let mut result = Args {
-			example: None,
+			script: None,
+			script_args: vec![],
+			plain: false,
 			debug: DebugFlags::NONE,
 		};
 
@@ -95,9 +169,11 @@ impl<'a> Parse<'a> for Args {
 
 			let key = input.consume_kind(Word)?;
 			match key.lexeme() {
+				"plain" => result.plain = true,
 				"example" => {
 					input.consume_kind(Equal)?;
-					result.example = Some(input.consume_kind(Word)?.lexeme().into());
+					let name = input.consume_kind(Word)?;
+					result.script = Some(Script::example(name.lexeme()));
 				}
 				"debug" => {
 					input.consume_kind(Equal)?;
@@ -115,12 +191,16 @@ impl<'a> Parse<'a> for Args {
 							Some(Token::Word("compile", _)) => {
 								result.debug |= DebugFlags::COMPILE;
 							}
+							Some(Token::Word("stress-gc", _)) => {
+								result.debug |= DebugFlags::STRESS_GC;
+							}
 							Some(Token::Boolean("true", _)) => {
 								result.debug |= DebugFlags::ALL;
 							}
 							Some(Token::Boolean("false", _)) => {
 								result.debug = DebugFlags::NONE;
 							}
+							Some(Token::Comma(..)) => {}
 							Some(other) => {
 								return Err(SpannedError {
 									message: "Unrecognized debug argument".into(),
@@ -2,7 +2,10 @@ use std::fmt;
 
 use nu_ansi_term::Color;
 
-use crate::repr::Value;
+use crate::{
+	compiler::{token_style, TokenKind},
+	repr::{Obj, ObjKind, Value},
+};
 
 pub trait FmtColored {
 	fn fmt_(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -17,7 +20,29 @@ impl FmtColored for Value {
 		match self {
 			Value::Number(n) => n.fmt_colored(),
 			Value::Bool(b) => b.fmt_colored(),
-			Value::Nil => Color::Cyan.italic().paint("nil").to_string(),
+			Value::Nil => token_style(TokenKind::Keyword, "nil")
+				.paint("nil")
+				.to_string(),
+			Value::Obj(obj) => obj.fmt_colored(),
+		}
+	}
+}
+
+impl FmtColored for Obj {
+	fn fmt_colored(&self) -> String {
+		match self.kind() {
+			ObjKind::String => self.as_string().unwrap().as_str().fmt_colored(),
+			ObjKind::Function
+			| ObjKind::Closure
+			| ObjKind::BoundMethod
+			| ObjKind::Native => Color::Blue.paint(self.to_string()).to_string(),
+			ObjKind::Class => Color::Yellow.paint(self.to_string()).to_string(),
+			ObjKind::Instance | ObjKind::Userdata => Color::LightYellow
+				.paint(self.to_string())
+				.to_string(),
+			ObjKind::Upvalue => Color::DarkGray
+				.paint(self.to_string())
+				.to_string(),
 		}
 	}
 }
@@ -34,25 +59,27 @@ impl FmtColored for f64 {
 			3
 		};
 
-		Color::Cyan
-			.paint(format!("{1:.0$}", prec, self))
+		let lexeme = format!("{1:.0$}", prec, self);
+		token_style(TokenKind::NumLit, &lexeme)
+			.paint(&lexeme)
 			.to_string()
 	}
 }
 
 impl FmtColored for bool {
 	fn fmt_colored(&self) -> String {
-		Color::Cyan
-			.italic()
-			.paint(format!("{}", self))
+		let lexeme = self.to_string();
+		token_style(TokenKind::Keyword, &lexeme)
+			.paint(&lexeme)
 			.to_string()
 	}
 }
 
 impl FmtColored for &str {
 	fn fmt_colored(&self) -> String {
-		Color::Green
-			.paint(format!(r#""{}""#, self))
+		let lexeme = format!(r#""{}""#, self);
+		token_style(TokenKind::StrLit, &lexeme)
+			.paint(&lexeme)
 			.to_string()
 	}
 }
@@ -0,0 +1,135 @@
+use std::{env, fs, io, path::PathBuf};
+
+/// Inputs submitted to the REPL, oldest first, which are saved to a file so that
+/// they carry over between sessions.
+pub(super) struct History {
+	entries: Vec<String>,
+	/// `None` if there's nowhere to save the history, e.g. if the user's data
+	/// directory is unknown
+	path: Option<PathBuf>,
+}
+
+impl History {
+	/// The number of entries kept, beyond which the oldest are dropped
+	pub(super) const MAX_ENTRIES: usize = 1000;
+
+	/// Loads the history from the user's data directory
+	pub(super) fn load() -> Self {
+		Self::open(data_dir().map(|dir| dir.join("lox").join("history")))
+	}
+
+	/// Loads the history saved at `path`, if there is one. A missing or unreadable
+	/// file just means starting from an empty history.
+	pub(super) fn open(path: Option<PathBuf>) -> Self {
+		let mut entries: Vec<String> = path
+			.as_deref()
+			.and_then(|path| fs::read_to_string(path).ok())
+			.map(|saved| saved.lines().map(unescape).collect())
+			.unwrap_or_default();
+
+		let excess = entries.len().saturating_sub(Self::MAX_ENTRIES);
+		entries.drain(..excess);
+
+		Self { entries, path }
+	}
+
+	pub(super) fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub(super) fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub(super) fn get(&self, idx: usize) -> Option<&str> {
+		self.entries.get(idx).map(String::as_str)
+	}
+
+	/// Adds an entry as the most recent, removing any earlier copies of it, and saves
+	/// the history. Blank entries aren't worth recalling, so they're skipped.
+	pub(super) fn push(&mut self, entry: &str) -> io::Result<()> {
+		if entry.trim().is_empty() {
+			return Ok(());
+		}
+
+		self.entries.retain(|existing| existing != entry);
+		self.entries.push(entry.into());
+
+		let excess = self
+			.entries
+			.len()
+			.saturating_sub(Self::MAX_ENTRIES);
+		self.entries.drain(..excess);
+
+		self.save()
+	}
+
+	/// The index of the most recent entry before `before` that contains `query`
+	pub(super) fn search(&self, query: &str, before: usize) -> Option<usize> {
+		self.entries[..before.min(self.entries.len())]
+			.iter()
+			.rposition(|entry| entry.contains(query))
+	}
+
+	fn save(&self) -> io::Result<()> {
+		let path = match &self.path {
+			Some(path) => path,
+			None => return Ok(()),
+		};
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+
+		let mut saved = String::new();
+		for entry in &self.entries {
+			saved.push_str(&escape(entry));
+			saved.push('\n');
+		}
+
+		fs::write(path, saved)
+	}
+}
+
+/// Where applications keep their data on this platform
+fn data_dir() -> Option<PathBuf> {
+	if cfg!(windows) {
+		env::var_os("APPDATA").map(PathBuf::from)
+	} else if cfg!(target_os = "macos") {
+		home_dir().map(|home| home.join("Library").join("Application Support"))
+	} else {
+		env::var_os("XDG_DATA_HOME")
+			.map(PathBuf::from)
+			.filter(|dir| dir.is_absolute())
+			.or_else(|| home_dir().map(|home| home.join(".local").join("share")))
+	}
+}
+
+fn home_dir() -> Option<PathBuf> {
+	env::var_os("HOME")
+		.map(PathBuf::from)
+		.filter(|home| home.is_absolute())
+}
+
+// Multi-line entries are saved on a single line, with their line breaks (and the
+// backslashes used to escape them) escaped
+
+fn escape(entry: &str) -> String {
+	entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(saved: &str) -> String {
+	let mut entry = String::with_capacity(saved.len());
+	let mut chars = saved.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		if c == '\\' {
+			if let Some(escaped) = chars.next_if(|&next| next == 'n' || next == '\\') {
+				entry.push(if escaped == 'n' { '\n' } else { '\\' });
+				continue;
+			}
+		}
+		entry.push(c);
+	}
+
+	entry
+}
@@ -1,5 +1,6 @@
 use std::{
 	io,
+	sync::atomic::{AtomicBool, Ordering},
 	thread::{self, JoinHandle},
 	time::Duration,
 };
@@ -10,23 +11,34 @@ use crossterm::{
 };
 use parking_lot::{Mutex, MutexGuard};
 
+use crate::vm;
+
 pub use self::{
-	args::{args, debug_flags, DebugFlags},
+	args::{args, debug_flags, DebugFlags, Script},
 	fmt_colored::FmtColored,
 	stdio::*,
 };
 
 mod args;
 mod fmt_colored;
+mod history;
 mod stdio;
 mod view;
 
+#[cfg(test)]
+mod tests;
+
 lazy_static! {
 	static ref STDIO: Mutex<Stdio> = Mutex::new(Stdio::new());
 }
 
+/// Set by `init`. Until then nothing touches the terminal, since stdout might not
+/// even be one.
+static TUI: AtomicBool = AtomicBool::new(false);
+
 pub fn init() -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
 	terminal::enable_raw_mode()?;
+	TUI.store(true, Ordering::Relaxed);
 
 	let mut stderr = io::stderr_locked();
 	execute!(
@@ -46,6 +58,32 @@ pub fn init() -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
 	Ok(handle)
 }
 
+/// Options for the VMs run from the command line, as set by the `--debug` flags
+pub fn vm_options() -> vm::Options {
+	vm::Options {
+		stress_gc: debug_flags().contains(DebugFlags::STRESS_GC),
+	}
+}
+
 pub fn stdio<'a>() -> MutexGuard<'a, Stdio> {
 	STDIO.lock()
 }
+
+/// Writes debug output to the terminal UI's debug area, or to stderr as plain text
+/// when the UI isn't running (e.g. in the plain REPL, or when running a script).
+pub fn write_debug(text: &str) -> anyhow::Result<()> {
+	let text = text.strip_suffix('\n').unwrap_or(text);
+
+	if TUI.load(Ordering::Relaxed) {
+		let mut stdio = stdio();
+		for line in text.split('\n') {
+			stdio.writeln(line, Area::Debug)?;
+		}
+		stdio.flush()
+	} else {
+		let text = strip_ansi_escapes::strip(text)?;
+		eprintln!("{}", String::from_utf8_lossy(&text));
+
+		Ok(())
+	}
+}
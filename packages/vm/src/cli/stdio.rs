@@ -1,7 +1,7 @@
 use std::{
 	fmt::Display,
 	io::{self, BufWriter, Write},
-	process,
+	iter, mem, process,
 	sync::mpsc::{self, Receiver, Sender},
 	time::Duration,
 };
@@ -15,19 +15,52 @@ use crossterm::{
 	QueueableCommand,
 };
 
-use crate::{debug::Repeat, repr::alloc::MemState};
+use crate::{compiler, debug::Repeat, repr::alloc::MemState};
 
-use super::view::{Rect, View};
+use super::{
+	history::History,
+	view::{Rect, View},
+};
 
+#[derive(Clone, Copy)]
 pub enum Area {
 	Output,
 	Debug,
 }
 
+/// An incremental reverse search through the history, started with Ctrl+R
+struct Search {
+	query: String,
+	/// The most recent entry containing the query, if any
+	found: Option<usize>,
+	/// The input from before the search, restored if the search is cancelled
+	draft: String,
+}
+
+/// An `io::Write` sink that forwards each complete line written to it to one of the
+/// TUI's views.
+pub struct AreaWriter {
+	area: Area,
+	buf: String,
+}
+
 pub struct Stdio {
 	target: BufWriter<io::Stdout>,
+	/// Finished lines of a multi-line input, which are submitted along with `input`
+	lines: Vec<String>,
+	/// The line of input being edited
 	input: String,
+	/// The cursor's position in `input`, counted in chars rather than bytes
 	cursor: u16,
+	/// Rows taken up by the input at the top of the screen
+	input_height: u16,
+	history: History,
+	/// The history entry shown in the input while stepping through the history with
+	/// Up/Down
+	recall: Option<usize>,
+	/// The input from before stepping through the history, to return to afterwards
+	draft: String,
+	search: Option<Search>,
 	stdin_rx: Option<Receiver<String>>,
 	stdin_tx: Sender<String>,
 	output: View,
@@ -62,8 +95,14 @@ impl Stdio {
 
 		Self {
 			target: BufWriter::with_capacity(w as usize * h as usize, io::stdout()),
+			lines: vec![],
 			input: String::with_capacity(w as usize - 6),
 			cursor: 0,
+			input_height: 1,
+			history: History::load(),
+			recall: None,
+			draft: String::new(),
+			search: None,
 			stdin_rx: Some(stdin_rx),
 			stdin_tx,
 			output,
@@ -161,8 +200,6 @@ impl Stdio {
 		queue!(
 			target,
 			style::SetForegroundColor(Color::DarkGrey),
-			cursor::MoveTo(0, 1),
-			style::Print(&divider),
 			cursor::MoveTo(0, h - 3),
 			style::Print(divider),
 			cursor::MoveTo(0, h - 2),
@@ -171,21 +208,118 @@ impl Stdio {
 			style::Print("    "),
 			style::Print(callout("Scroll Debug", "Ctrl+Shift+Up/Down")),
 			style::ResetColor,
-			cursor::MoveTo(0, 0),
-			style::SetForegroundColor(Color::Blue),
-			style::SetAttribute(Attribute::Bold),
-			style::Print("lox"),
-			style::SetAttribute(Attribute::Reset),
-			style::SetForegroundColor(Color::DarkGrey),
-			style::Print(' '),
-			style::Print('\u{276F}'),
-			style::Print(' '),
-			cursor::SavePosition,
+			style::Print("    "),
+			style::Print(callout("Newline", "Shift+Enter")),
+			style::ResetColor,
 		)?;
 
+		self.render_input()?;
+		queue!(&mut self.target, cursor::SavePosition)?;
+
 		self.flush()
 	}
 
+	/// Redraws the input at the top of the screen, growing or shrinking the input
+	/// region to fit its lines (up to half of the screen), and moves the cursor back
+	/// to its place in the input.
+	fn render_input(&mut self) -> anyhow::Result<()> {
+		let (w, h) = self.size;
+		let max_height = ((h - 5) / 2).max(1);
+		let height = (self.lines.len() as u16 + 1).min(max_height);
+
+		if height != self.input_height {
+			self.input_height = height;
+			self.output
+				.move_top(height + 1, &mut self.target)?;
+			self.debug
+				.move_top(height + 1, &mut self.target)?;
+		}
+
+		// Only the last lines are shown if they don't all fit
+		let hidden = self.lines.len() + 1 - height as usize;
+		let highlighted = compiler::highlight(&self.input_text());
+		let lines = self
+			.lines
+			.iter()
+			.chain(iter::once(&self.input))
+			.zip(highlighted)
+			.enumerate()
+			.skip(hidden);
+
+		let target = &mut self.target;
+		for (row, (idx, (line, highlighted))) in lines.enumerate() {
+			queue!(target, cursor::MoveTo(0, row as u16))?;
+			if idx == 0 {
+				queue!(
+					target,
+					style::SetForegroundColor(Color::Blue),
+					style::SetAttribute(Attribute::Bold),
+					style::Print("lox"),
+					style::SetAttribute(Attribute::Reset),
+					style::SetForegroundColor(Color::DarkGrey),
+					style::Print(' '),
+					style::Print('\u{276F}'),
+					style::Print(' '),
+				)?;
+			} else {
+				queue!(
+					target,
+					style::SetForegroundColor(Color::DarkGrey),
+					style::Print("    \u{2026} "),
+				)?;
+			}
+
+			let fill = (w as usize).saturating_sub(line.chars().count() + 6);
+			queue!(
+				target,
+				style::ResetColor,
+				style::Print(highlighted),
+				style::Print(' '.repeat(fill)),
+			)?;
+		}
+
+		let divider = '\u{2014}'.repeat(w as usize);
+		queue!(
+			target,
+			style::SetForegroundColor(Color::DarkGrey),
+			cursor::MoveTo(0, height),
+			style::Print(divider),
+			style::ResetColor,
+		)?;
+
+		// The query of a search under way is shown on the divider, where it's edited
+		match &self.search {
+			Some(search) => {
+				let status = match search.found {
+					Some(_) => format!(" reverse search: {}", search.query),
+					None => format!(" failed reverse search: {}", search.query),
+				};
+				let col = status.chars().count() as u16 + 2;
+
+				queue!(
+					target,
+					cursor::MoveTo(2, height),
+					style::SetForegroundColor(Color::Yellow),
+					style::Print(status),
+					style::Print(' '),
+					style::ResetColor,
+					cursor::MoveTo(col, height),
+				)?;
+			}
+			None => {
+				let cursor = self.cursor;
+				queue!(target, cursor::MoveTo(cursor + 6, height - 1))?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The row of the line being edited
+	fn input_row(&self) -> u16 {
+		self.input_height - 1
+	}
+
 	pub(super) fn poll_events(&mut self) -> anyhow::Result<()> {
 		if event::poll(Duration::from_millis(5))? {
 			match event::read()? {
@@ -195,30 +329,39 @@ impl Stdio {
 					if matches!(code, Char('c'))
 						&& modifiers.contains(KeyModifiers::CONTROL)
 					{
-						self.exit();
+						self.exit(0);
 					}
 
 					queue!(&mut self.target, style::ResetColor)?;
 
-					match code {
-						Backspace => self.backspace()?,
-						Enter => self.submit_stdin()?,
-						Left => self.cursor_left()?,
-						Right => self.cursor_right()?,
-						Up => self.scroll_kb(1, modifiers)?,
-						Down => self.scroll_kb(-1, modifiers)?,
-						Home => self.home()?,
-						End => self.end()?,
-						PageUp => {}   // TODO
-						PageDown => {} // TODO
-						Tab => {}      // TODO
-						BackTab => {}  // TODO
-						Delete => self.delete()?,
-						Insert => {} // TODO
-						F(_) => {}   // TODO
-						Char(c) => self.key(c)?,
-						Null => {} // TODO
-						Esc => self.exit(),
+					let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+					if matches!(code, Char('r')) && ctrl {
+						self.reverse_search()?;
+					} else if self.search.is_some() {
+						self.search_key(code)?;
+					} else {
+						match code {
+							Backspace => self.backspace()?,
+							Enter => self.enter(modifiers)?,
+							Left => self.cursor_left()?,
+							Right => self.cursor_right()?,
+							Up if modifiers.is_empty() => self.recall_prev()?,
+							Down if modifiers.is_empty() => self.recall_next()?,
+							Up => self.scroll_kb(1, modifiers)?,
+							Down => self.scroll_kb(-1, modifiers)?,
+							Home => self.home()?,
+							End => self.end()?,
+							PageUp => {}   // TODO
+							PageDown => {} // TODO
+							Tab => {}      // TODO
+							BackTab => {}  // TODO
+							Delete => self.delete()?,
+							Insert => {} // TODO
+							F(_) => {}   // TODO
+							Char(c) => self.key(c)?,
+							Null => {} // TODO
+							Esc => self.exit(0),
+						}
 					}
 				}
 				Event::Mouse(evt @ MouseEvent { kind, .. }) => match kind {
@@ -236,49 +379,187 @@ impl Stdio {
 	}
 
 	fn backspace(&mut self) -> anyhow::Result<()> {
-		if self.cursor == self.input.len() as u16 && self.input.pop().is_some() {
+		if self.cursor > 0 {
 			self.cursor -= 1;
+			self.input.remove(self.cursor_offset());
+		} else if let Some(mut prev) = self.lines.pop() {
+			// Join the line onto the end of the previous one
+			self.cursor = prev.chars().count() as u16;
+			prev.push_str(&self.input);
+			self.input = prev;
+		} else {
+			return Ok(());
+		}
 
-			queue!(
-				&mut self.target,
-				cursor::MoveLeft(1),
-				style::Print(' '),
-				cursor::MoveLeft(1),
-			)?;
-		} else if !self.input.is_empty() {
-			self.cursor -= 1;
-			let cursor = self.cursor;
+		self.render_input()
+	}
 
-			self.input.remove(cursor as usize);
-			let mut rem = (&self.input[cursor as usize..]).to_owned();
-			rem.push(' ');
+	/// Submits the input, unless it's unfinished (e.g. an unclosed block) or Shift is
+	/// held, in which case a new line is started instead.
+	fn enter(&mut self, modifiers: KeyModifiers) -> anyhow::Result<()> {
+		if modifiers.contains(KeyModifiers::SHIFT) {
+			return self.newline();
+		}
 
-			queue!(
-				&mut self.target,
-				cursor::MoveLeft(1),
-				style::Print(rem),
-				cursor::MoveTo(cursor + 6, 0),
-			)?;
+		if compiler::is_incomplete(&self.input_text()) {
+			self.newline()
+		} else {
+			self.submit_stdin()
 		}
+	}
 
-		Ok(())
+	/// Splits the line being edited at the cursor
+	fn newline(&mut self) -> anyhow::Result<()> {
+		let rest = self.input.split_off(self.cursor_offset());
+		self.lines
+			.push(mem::replace(&mut self.input, rest));
+		self.cursor = 0;
+
+		self.render_input()
 	}
 
 	fn submit_stdin(&mut self) -> anyhow::Result<()> {
-		self.stdin_tx.send(self.input.clone()).unwrap();
-		self.cursor = 0;
+		let input = self.input_text();
+		self.lines.clear();
 		self.input.clear();
+		self.cursor = 0;
+		self.recall = None;
 
-		let (width, _) = self.size;
+		// Failing to save the history shouldn't get in the way of running the input
+		let _ = self.history.push(&input);
+		self.stdin_tx.send(input).unwrap();
 
-		queue!(
-			&mut self.target,
-			cursor::MoveTo(6, 0),
-			style::Print(' '.repeat(width as usize - 6)),
-			cursor::MoveTo(6, 0),
-		)?;
+		self.render_input()
+	}
 
-		Ok(())
+	/// All of the lines of input, including the one being edited
+	fn input_text(&self) -> String {
+		let mut text = String::new();
+		for line in &self.lines {
+			text.push_str(line);
+			text.push('\n');
+		}
+		text.push_str(&self.input);
+
+		text
+	}
+
+	/// Replaces the input, leaving the cursor at the end of it
+	fn set_input(&mut self, text: &str) {
+		let mut lines: Vec<String> = text.split('\n').map(String::from).collect();
+		self.input = lines.pop().unwrap_or_default();
+		self.lines = lines;
+		self.cursor = self.input_len();
+	}
+
+	/// Steps back through the history, keeping the current input to come back to
+	fn recall_prev(&mut self) -> anyhow::Result<()> {
+		let idx = match self.recall {
+			None if !self.history.is_empty() => {
+				self.draft = self.input_text();
+				self.history.len() - 1
+			}
+			Some(idx) if idx > 0 => idx - 1,
+			_ => return Ok(()),
+		};
+
+		let entry = self.history.get(idx).unwrap().to_owned();
+		self.recall = Some(idx);
+		self.set_input(&entry);
+
+		self.render_input()
+	}
+
+	/// Steps forward through the history, back to the input from before recalling
+	fn recall_next(&mut self) -> anyhow::Result<()> {
+		let entry = match self.recall {
+			Some(idx) if idx + 1 < self.history.len() => {
+				self.recall = Some(idx + 1);
+				self.history.get(idx + 1).unwrap().to_owned()
+			}
+			Some(_) => {
+				self.recall = None;
+				mem::take(&mut self.draft)
+			}
+			None => return Ok(()),
+		};
+
+		self.set_input(&entry);
+
+		self.render_input()
+	}
+
+	/// Starts a reverse search, or finds the next older match if one is under way
+	fn reverse_search(&mut self) -> anyhow::Result<()> {
+		if let Some(search) = &mut self.search {
+			let before = search.found.unwrap_or(self.history.len());
+			if let Some(found) = self.history.search(&search.query, before) {
+				search.found = Some(found);
+			}
+		} else {
+			self.search = Some(Search {
+				query: String::new(),
+				found: None,
+				draft: self.input_text(),
+			});
+		}
+
+		self.show_search()
+	}
+
+	/// Edits the query of the search under way. Enter runs the match, Esc cancels the
+	/// search, and any other key leaves the match in the input to be edited.
+	fn search_key(&mut self, code: KeyCode) -> anyhow::Result<()> {
+		let search = self.search.as_mut().unwrap();
+		match code {
+			KeyCode::Char(c) => search.query.push(c),
+			KeyCode::Backspace => {
+				search.query.pop();
+			}
+			KeyCode::Esc => {
+				let search = self.search.take().unwrap();
+				self.set_input(&search.draft);
+				return self.render_input();
+			}
+			KeyCode::Enter => {
+				self.end_search();
+				return self.enter(KeyModifiers::NONE);
+			}
+			_ => {
+				self.end_search();
+				return self.render_input();
+			}
+		}
+
+		search.found = self
+			.history
+			.search(&search.query, self.history.len());
+
+		self.show_search()
+	}
+
+	/// Shows the search's match (or the original input, if nothing matches) in the
+	/// input
+	fn show_search(&mut self) -> anyhow::Result<()> {
+		let search = self.search.as_ref().unwrap();
+		let text = match search.found {
+			Some(idx) => self.history.get(idx).unwrap().to_owned(),
+			None => search.draft.clone(),
+		};
+		self.set_input(&text);
+
+		self.render_input()
+	}
+
+	/// Ends the search, keeping its match in the input
+	fn end_search(&mut self) {
+		if let Some(search) = self.search.take() {
+			let text = match search.found {
+				Some(idx) => self.history.get(idx).unwrap().to_owned(),
+				None => search.draft,
+			};
+			self.set_input(&text);
+		}
 	}
 
 	fn cursor_left(&mut self) -> anyhow::Result<()> {
@@ -293,7 +574,7 @@ impl Stdio {
 
 	fn cursor_right(&mut self) -> anyhow::Result<()> {
 		// TODO: jump between words when Ctrl is held
-		if self.cursor < self.input.len() as u16 {
+		if self.cursor < self.input_len() {
 			self.cursor += 1;
 			queue!(&mut self.target, cursor::MoveRight(1))?;
 		}
@@ -303,65 +584,48 @@ impl Stdio {
 
 	fn home(&mut self) -> anyhow::Result<()> {
 		self.cursor = 0;
-		queue!(&mut self.target, cursor::MoveTo(6, 0))?;
+		let row = self.input_row();
+
+		queue!(&mut self.target, cursor::MoveTo(6, row))?;
 		Ok(())
 	}
 
 	fn end(&mut self) -> anyhow::Result<()> {
-		self.cursor = self.input.len() as u16;
-		let input_len = self.input.len();
+		self.cursor = self.input_len();
+		let col = self.cursor + 6;
+		let row = self.input_row();
 
-		queue!(&mut self.target, cursor::MoveTo(input_len as u16 + 6, 0))?;
+		queue!(&mut self.target, cursor::MoveTo(col, row))?;
 		Ok(())
 	}
 
 	fn delete(&mut self) -> anyhow::Result<()> {
-		if !self.input.is_empty() {
-			let cursor = self.cursor;
-			let (width, _) = self.size;
-
-			if cursor == self.input.len() as u16 - 1 {
-				let _ = self.input.pop();
-				queue!(&mut self.target, style::Print(' '), cursor::MoveLeft(1))?;
-			} else {
-				self.input.remove(cursor as usize);
-
-				let del_len = width - cursor - 6;
-				let fill = ' '.repeat(del_len as usize);
-				let rem = (&self.input[cursor as usize..]).to_owned();
-
-				queue!(
-					&mut self.target,
-					cursor::MoveTo(cursor + 6, 0),
-					style::Print(fill),
-					cursor::MoveTo(cursor + 6, 0),
-					style::Print(rem),
-					cursor::MoveTo(cursor + 6, 0),
-				)?;
-			}
+		if self.cursor < self.input_len() {
+			self.input.remove(self.cursor_offset());
+			self.render_input()?;
 		}
 
 		Ok(())
 	}
 
 	fn key(&mut self, key: char) -> anyhow::Result<()> {
-		let cursor = self.cursor;
+		self.input.insert(self.cursor_offset(), key);
 		self.cursor += 1;
 
-		if cursor == self.input.len() as u16 {
-			self.input.push(key);
-			queue!(&mut self.target, style::Print(key))?;
-		} else {
-			self.input.insert(cursor as usize, key);
-			let rem = (&self.input[cursor as usize..]).to_owned();
-			queue!(
-				&mut self.target,
-				style::Print(rem),
-				cursor::MoveTo(cursor + 7, 0)
-			)?;
-		}
+		self.render_input()
+	}
 
-		Ok(())
+	/// The length of the line being edited, in chars
+	fn input_len(&self) -> u16 {
+		self.input.chars().count() as u16
+	}
+
+	/// The byte offset in `input` of the char at the cursor
+	fn cursor_offset(&self) -> usize {
+		self.input
+			.char_indices()
+			.nth(self.cursor as usize)
+			.map_or(self.input.len(), |(offset, _)| offset)
 	}
 
 	/// `delta` is -1 to reveal past messages, or +1 to reveal newer.
@@ -418,9 +682,37 @@ impl Stdio {
 		Ok(())
 	}
 
-	fn exit(&mut self) {
+	pub fn exit(&mut self, code: i32) {
 		execute!(&mut self.target, terminal::Clear(ClearType::All)).unwrap();
-		process::exit(0);
+		process::exit(code);
+	}
+}
+
+impl AreaWriter {
+	pub fn new(area: Area) -> Self {
+		Self {
+			area,
+			buf: String::new(),
+		}
+	}
+}
+
+impl Write for AreaWriter {
+	fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+		self.buf.push_str(&String::from_utf8_lossy(bytes));
+
+		while let Some(idx) = self.buf.find('\n') {
+			let line = self.buf.drain(..=idx).collect::<String>();
+			super::stdio()
+				.writeln(line.trim_end(), self.area)
+				.map_err(io::Error::other)?;
+		}
+
+		Ok(bytes.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		super::stdio().flush().map_err(io::Error::other)
 	}
 }
 
@@ -0,0 +1,149 @@
+use std::{env, fs, path::PathBuf, process};
+
+use super::{args, history::History, DebugFlags, Script};
+
+fn parse(raw: &[&str]) -> anyhow::Result<args::Args> {
+	args::from_iter(raw.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn no_args_starts_the_repl() {
+	let args = parse(&[]).unwrap();
+
+	assert_eq!(args.script, None);
+	assert!(args.script_args.is_empty());
+	assert!(!args.plain);
+	assert_eq!(args.debug, DebugFlags::NONE);
+}
+
+#[test]
+fn script_path_and_args() {
+	let args = parse(&[
+		"--debug=parse,exec",
+		"../scripts/hello world.lox",
+		"--not-an-option",
+		"-",
+	])
+	.unwrap();
+
+	assert_eq!(
+		args.script,
+		Some(Script::File("../scripts/hello world.lox".into()))
+	);
+	assert_eq!(args.script_args, ["--not-an-option", "-"]);
+	assert_eq!(args.debug, DebugFlags::PARSE | DebugFlags::EXEC);
+}
+
+#[test]
+fn plain_repl() {
+	let args = parse(&["--plain", "--debug=exec"]).unwrap();
+
+	assert!(args.plain);
+	assert_eq!(args.script, None);
+	assert_eq!(args.debug, DebugFlags::EXEC);
+}
+
+#[test]
+fn script_from_stdin() {
+	let args = parse(&["-", "arg"]).unwrap();
+
+	assert_eq!(args.script, Some(Script::Stdin));
+	assert_eq!(args.script_args, ["arg"]);
+}
+
+#[test]
+fn examples_resolve_from_any_directory() {
+	let args = parse(&["--example=fizzbuzz2"]).unwrap();
+
+	match args.script {
+		Some(Script::File(path)) => {
+			assert!(path.ends_with("examples/fizzbuzz2.lox"));
+			assert!(path.is_absolute());
+		}
+		other => panic!("Expected an example script, found {:?}", other),
+	}
+}
+
+#[test]
+fn invalid_args() {
+	assert!(parse(&["--nope=1"]).is_err());
+	assert!(parse(&["--example=fizzbuzz2", "script.lox"]).is_err());
+}
+
+/// A temporary directory that's unique to the test, and removed along with its
+/// contents when the test is done
+struct TempDir(PathBuf);
+
+impl TempDir {
+	fn new(test: &str) -> Self {
+		let path = env::temp_dir().join(format!("lox-{}-{}", process::id(), test));
+		let _ = fs::remove_dir_all(&path);
+
+		Self(path)
+	}
+
+	fn join(&self, name: &str) -> PathBuf {
+		self.0.join(name)
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.0);
+	}
+}
+
+#[test]
+fn history_is_deduplicated() {
+	let mut history = History::open(None);
+	for entry in ["print 1;", "print 2;", "print 1;", "  ", ""] {
+		history.push(entry).unwrap();
+	}
+
+	assert_eq!(history.len(), 2);
+	assert_eq!(history.get(0), Some("print 2;"));
+	assert_eq!(history.get(1), Some("print 1;"));
+}
+
+#[test]
+fn history_is_capped() {
+	let mut history = History::open(None);
+	for n in 0..History::MAX_ENTRIES + 10 {
+		history.push(&format!("print {};", n)).unwrap();
+	}
+
+	assert_eq!(history.len(), History::MAX_ENTRIES);
+	assert_eq!(history.get(0), Some("print 10;"));
+}
+
+#[test]
+fn history_persists() {
+	let dir = TempDir::new("history_persists");
+	let path = dir.join("history");
+
+	let mut history = History::open(Some(path.clone()));
+	assert!(history.is_empty());
+	history
+		.push("fun f() {\n\tprint \"\\n\";\n}")
+		.unwrap();
+	history.push("f();").unwrap();
+
+	let history = History::open(Some(path));
+	assert_eq!(history.len(), 2);
+	assert_eq!(history.get(0), Some("fun f() {\n\tprint \"\\n\";\n}"));
+	assert_eq!(history.get(1), Some("f();"));
+}
+
+#[test]
+fn history_search() {
+	let mut history = History::open(None);
+	for entry in ["var a = 1;", "print a;", "var b = 2;", "print b;"] {
+		history.push(entry).unwrap();
+	}
+
+	let found = history.search("var", history.len());
+	assert_eq!(found, Some(2));
+	assert_eq!(history.search("var", found.unwrap()), Some(0));
+	assert_eq!(history.search("var", 0), None);
+	assert_eq!(history.search("nope", history.len()), None);
+}
@@ -171,6 +171,35 @@ impl View {
 		Ok(())
 	}
 
+	/// Moves the view's top edge to row `y`, keeping its bottom edge where it is, and
+	/// redraws its visible messages to fit.
+	pub(super) fn move_top<T>(&mut self, y: u16, target: &mut T) -> anyhow::Result<()>
+	where T: QueueableCommand {
+		let bottom = self.layout.y + self.layout.height;
+		self.layout.y = y;
+		self.layout.height = bottom - y;
+
+		let height = self.layout.height as usize;
+		self.top = self
+			.top
+			.min(self.messages.len().saturating_sub(height));
+
+		let mut lines = self.messages.iter().skip(self.top);
+		for row in y..bottom {
+			target.queue(cursor::MoveTo(self.layout.x, row))?;
+			match lines.next() {
+				Some(line) => {
+					let len = strip_ansi_escapes::strip(line)?.len();
+					target.queue(style::Print(line))?;
+					self.clear_line_from(target, self.layout.x + len as u16 + 1)?;
+				}
+				None => self.clear_line(target)?,
+			}
+		}
+
+		Ok(())
+	}
+
 	fn top_line(&mut self) -> &mut String {
 		if self.messages.is_empty() {
 			self.messages.push_front(String::new());
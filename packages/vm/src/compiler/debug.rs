@@ -9,16 +9,18 @@ use parking_lot::{Mutex, MutexGuard};
 
 use crate::{
 	chunk::OpCode,
-	cli::{self, Area, DebugFlags},
+	cli::{self, DebugFlags, FmtColored},
 	compiler::lexer::TokenKind,
 	debug::Repeat,
-	repr::Value,
+	repr::{Obj, Value},
 };
 
 use super::{
 	lexer::{Stream, Token},
 	pratt::HashToken,
 	prec::Prec,
+	scope::Upvalue,
+	token_style,
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -36,9 +38,7 @@ lazy_static! {
 
 pub(super) fn write_header(_: &str) {
 	if cli::debug_flags().intersects(DebugFlags::COMPILE) {
-		let mut stdio = cli::stdio();
-		stdio.writeln("", Area::Debug).unwrap();
-		stdio.flush().unwrap();
+		cli::write_debug("").unwrap();
 	}
 }
 
@@ -114,11 +114,82 @@ pub(super) fn codegen_pair(name: &'static str, pair: (OpCode, OpCode), _: Span)
 	}
 }
 
+pub(super) fn codegen_operand(name: &'static str, op: OpCode, operand: u8, _: Span) {
+	if should_print(DebugFlags::CODEGEN) {
+		write_label("codegen");
+		write_fn_call(name);
+		write_byte(op as u8);
+		write_opcode(op);
+		write_byte(operand);
+		endl();
+	}
+}
+
+pub(super) fn codegen_loop(name: &'static str, loop_start: usize, _: Span, _: &Stream) {
+	if should_print(DebugFlags::CODEGEN) {
+		write_label("codegen");
+		write_fn_call(name);
+		write_byte(OpCode::Loop as u8);
+		write_opcode(OpCode::Loop);
+		write_operator(&format!("-> {:#06x}", loop_start));
+		endl();
+	}
+}
+
 pub(super) fn codegen_const(name: &'static str, value: Value, _: Span) {
 	if should_print(DebugFlags::CODEGEN) {
 		write_label("codegen");
 		write_fn_call(name);
-		write_number(&format!("{}", value));
+		write_value(value);
+		endl();
+	}
+}
+
+pub(super) fn codegen_closure(
+	name: &'static str,
+	function: Obj,
+	upvalues: &[Upvalue],
+	_: Span,
+) {
+	if should_print(DebugFlags::CODEGEN) {
+		write_label("codegen");
+		write_fn_call(name);
+		write_byte(OpCode::Closure as u8);
+		write_opcode(OpCode::Closure);
+		write_value(Value::Obj(function));
+		for upvalue in upvalues {
+			let kind = if upvalue.is_local { "local" } else { "upvalue" };
+			write_operator(&format!("{} {}", kind, upvalue.index));
+		}
+		endl();
+	}
+}
+
+pub(super) fn codegen_invoke(
+	name: &'static str,
+	op: OpCode,
+	handle: usize,
+	argc: u8,
+	_: Span,
+) {
+	if should_print(DebugFlags::CODEGEN) {
+		write_label("codegen");
+		write_fn_call(name);
+		write_byte(op as u8);
+		write_opcode(op);
+		write_handle(handle);
+		write_operator(&format!("({} args)", argc));
+		endl();
+	}
+}
+
+pub(super) fn codegen_indexed(name: &'static str, op: OpCode, handle: usize, _: Span) {
+	if should_print(DebugFlags::CODEGEN) {
+		write_label("codegen");
+		write_fn_call(name);
+		write_byte(op as u8);
+		write_opcode(op);
+		write_handle(handle);
 		endl();
 	}
 }
@@ -173,35 +244,20 @@ fn write_prec(prec: Prec) {
 fn write_token(token: Token) {
 	use TokenKind::*;
 
-	match token.kind() {
-		NumLit => {
-			write_enum_variant("NumLit");
-			write_number(token.lexeme());
-		}
-		Operator => {
-			write_enum_variant("Operator");
-			write_operator_bright(token.lexeme());
-		}
-		Punct => {
-			write_enum_variant("Punct");
-			write_operator(token.lexeme());
-		}
-		Brace => {
-			write_enum_variant("Brace");
-			write_operator_bright(token.lexeme());
-		}
-		Keyword => {
-			write_enum_variant("Keyword");
-			let lex = token.lexeme();
-			match lex {
-				"true" | "false" | "nil" => {
-					write_language_constant(lex);
-				}
-				_ => write_keyword(lex),
-			}
-		}
-		_ => unimplemented!(),
-	};
+	write_enum_variant(match token.kind() {
+		NumLit => "NumLit",
+		StrLit => "StrLit",
+		Operator => "Operator",
+		Punct => "Punct",
+		Brace => "Brace",
+		Keyword => "Keyword",
+		Ident => "Ident",
+		Comment => "Comment",
+	});
+
+	let lex = token.lexeme();
+	let mut buf = buf();
+	write!(&mut buf, "{} ", token_style(token.kind(), lex).paint(lex)).unwrap();
 }
 
 fn write_rule_type(rule_type: RuleType) {
@@ -228,19 +284,24 @@ fn write_byte(byte: u8) {
 	.unwrap();
 }
 
-fn write_number(lex: &str) {
+fn write_handle(handle: usize) {
 	let mut buf = buf();
-	write!(&mut buf, "{} ", Color::Cyan.paint(lex)).unwrap();
+	write!(
+		&mut buf,
+		"{}",
+		Color::DarkGray.paint(format!("#{} ", handle))
+	)
+	.unwrap();
 }
 
-fn write_operator(lex: &str) {
+fn write_value(value: Value) {
 	let mut buf = buf();
-	write!(&mut buf, "{} ", Color::DarkGray.paint(lex)).unwrap();
+	write!(&mut buf, "{} ", value.fmt_colored()).unwrap();
 }
 
-fn write_operator_bright(lex: &str) {
+fn write_operator(lex: &str) {
 	let mut buf = buf();
-	write!(&mut buf, "{} ", Color::LightGray.paint(lex)).unwrap();
+	write!(&mut buf, "{} ", Color::DarkGray.paint(lex)).unwrap();
 }
 
 fn write_enum_variant(name: &str) {
@@ -248,16 +309,6 @@ fn write_enum_variant(name: &str) {
 	write!(&mut buf, "{} ", Color::DarkGray.italic().paint(name)).unwrap();
 }
 
-fn write_language_constant(lex: &str) {
-	let mut buf = buf();
-	write!(&mut buf, "{} ", Color::Cyan.italic().paint(lex)).unwrap();
-}
-
-fn write_keyword(lex: &str) {
-	let mut buf = buf();
-	write!(&mut buf, "{} ", Color::Magenta.italic().paint(lex)).unwrap();
-}
-
 fn endl() {
 	let mut buf = buf();
 	writeln!(&mut buf).unwrap();
@@ -265,13 +316,11 @@ fn endl() {
 
 pub(super) fn flush() {
 	let mut buf = buf();
-	let mut stdio = cli::stdio();
-
-	for line in buf.clone().lines() {
-		stdio.writeln(line, Area::Debug).unwrap();
+	if buf.is_empty() {
+		return;
 	}
-	stdio.flush().unwrap();
 
+	cli::write_debug(&buf).unwrap();
 	buf.clear();
 }
 
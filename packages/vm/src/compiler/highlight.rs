@@ -0,0 +1,103 @@
+use gramatika::{Spanned, Token as _};
+use nu_ansi_term::{Color, Style};
+
+use super::lexer::{Stream, TokenKind};
+
+/// Colors Lox source code for display, e.g. as it's being typed into the REPL,
+/// returning each of its lines with ANSI styling. Anything the lexer doesn't
+/// recognize (including the rest of the source after an unterminated string) is
+/// colored red.
+pub fn highlight(src: &str) -> Vec<String> {
+	let lines: Vec<Vec<char>> = src
+		.split('\n')
+		.map(|line| line.chars().collect())
+		.collect();
+	let mut styles: Vec<Vec<Option<Style>>> = lines
+		.iter()
+		.map(|line| vec![None; line.len()])
+		.collect();
+
+	let mut stream = Stream::from(src.to_owned());
+	for token in stream.by_ref() {
+		let span = token.span();
+		let style = token_style(token.kind(), token.lexeme());
+
+		for line in span.start.line..=span.end.line.min(lines.len() - 1) {
+			let start = if line == span.start.line {
+				span.start.character
+			} else {
+				0
+			};
+			let end = if line == span.end.line {
+				span.end.character
+			} else {
+				lines[line].len()
+			};
+
+			for slot in styles[line].iter_mut().take(end).skip(start) {
+				*slot = Some(style);
+			}
+		}
+	}
+
+	let error = Style::new().fg(Color::Red);
+	let mut unterminated = false;
+	for (line, line_styles) in lines.iter().zip(styles.iter_mut()) {
+		for (&c, slot) in line.iter().zip(line_styles.iter_mut()) {
+			if unterminated || (slot.is_none() && !c.is_whitespace()) {
+				unterminated |= c == '"';
+				*slot = Some(error);
+			}
+		}
+	}
+
+	lines
+		.iter()
+		.zip(styles)
+		.map(|(line, line_styles)| paint_line(line, &line_styles))
+		.collect()
+}
+
+/// The palette for Lox code everywhere it's displayed: in the REPL, in the
+/// compiler's debug output, and for values (with `FmtColored`), which are styled
+/// like the literals that would produce them.
+pub(crate) fn token_style(kind: TokenKind, lexeme: &str) -> Style {
+	use TokenKind::*;
+
+	match kind {
+		NumLit => Color::Cyan.normal(),
+		StrLit => Color::Green.normal(),
+		Operator | Brace => Color::LightGray.normal(),
+		Punct | Comment => Color::DarkGray.normal(),
+		Keyword => match lexeme {
+			"true" | "false" | "nil" => Color::Cyan.italic(),
+			_ => Color::Magenta.italic(),
+		},
+		Ident => Color::LightBlue.normal(),
+	}
+}
+
+/// Paints each run of characters with the same style in one go
+fn paint_line(line: &[char], styles: &[Option<Style>]) -> String {
+	let mut result = String::new();
+	let mut start = 0;
+
+	while start < line.len() {
+		let style = styles[start];
+		let len = styles[start..]
+			.iter()
+			.take_while(|&&next| next == style)
+			.count();
+		let run = line[start..start + len]
+			.iter()
+			.collect::<String>();
+
+		match style {
+			Some(style) => result.push_str(&style.paint(run).to_string()),
+			None => result.push_str(&run),
+		}
+		start += len;
+	}
+
+	result
+}
@@ -28,7 +28,7 @@ pub enum Token<'a> {
 	#[pattern = "[0-9][.0-9]*"]
 	NumLit(&'a str, Span),
 
-	#[pattern = r#""[^"]+""#]
+	#[pattern = r#""[^"]*""#]
 	StrLit(&'a str, Span),
 }
 
@@ -37,3 +37,65 @@ impl<'a> fmt::Debug for Token<'a> {
 		<Self as DebugLisp>::fmt(self, f, 0)
 	}
 }
+
+/// Blanks out `//` comments (outside of string literals) with whitespace, so the
+/// parser never has to deal with comment tokens while every other token keeps its
+/// original span.
+pub fn strip_comments(src: &str) -> String {
+	let mut result = String::with_capacity(src.len());
+	let mut chars = src.chars().peekable();
+	let mut in_string = false;
+
+	while let Some(c) = chars.next() {
+		match c {
+			'"' => {
+				in_string = !in_string;
+				result.push(c);
+			}
+			'/' if !in_string && chars.peek() == Some(&'/') => {
+				result.push(' ');
+				while chars
+					.next_if(|&next| next != '\n' && next != '\r')
+					.is_some()
+				{
+					result.push(' ');
+				}
+			}
+			_ => result.push(c),
+		}
+	}
+
+	result
+}
+
+/// Whether the source looks like the start of a longer program rather than a
+/// finished one, i.e. it has unclosed braces or parentheses, an unterminated string,
+/// or ends with an operator that's missing its right-hand side. Doesn't check
+/// whether the source is otherwise valid.
+#[cfg(feature = "cli")]
+pub fn is_incomplete(src: &str) -> bool {
+	let src = strip_comments(src);
+	if src.matches('"').count() % 2 == 1 {
+		return true;
+	}
+
+	// Tokens borrow from the stream, so it has to outlive `last`
+	let mut stream = Stream::from(src);
+	let mut depth = 0isize;
+	let mut last = None;
+	for token in stream.by_ref() {
+		match token {
+			Token::Brace("(" | "{", _) => depth += 1,
+			Token::Brace(")" | "}", _) => depth -= 1,
+			_ => {}
+		}
+		last = Some(token);
+	}
+
+	depth > 0
+		|| matches!(
+			last,
+			Some(Token::Operator(..) | Token::Punct("." | ",", _))
+				| Some(Token::Keyword("and" | "or", _))
+		)
+}
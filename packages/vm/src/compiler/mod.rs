@@ -1,83 +1,402 @@
-use gramatika::{Parse, ParseStreamer, Result, Span};
+use std::rc::Rc;
+
+use gramatika::{ParseStreamer, Span, Spanned};
 use macro_utils::trace;
 
 use crate::{
-	chunk::{Chunk, OpCode},
-	compiler::pratt::PrattParser,
-	repr::Value,
+	chunk::{Chunk, JumpError, OpCode},
+	compiler::stmt::StmtParser,
+	repr::{Heap, Obj, ObjFunction, Object, Trace, Tracer, Value},
+	vm::{Code, Diagnostic},
 	*,
 };
 
-use self::lexer::Stream;
+use self::{
+	lexer::{Stream, Token},
+	scope::{Local, Upvalue},
+};
 
 #[macro_use]
 mod lexer;
 
-#[cfg(debug_assertions)]
+#[cfg(feature = "cli")]
+pub(crate) use self::{
+	highlight::{highlight, token_style},
+	lexer::{is_incomplete, TokenKind},
+};
+
+#[cfg(all(debug_assertions, feature = "cli"))]
 mod debug;
 
+#[cfg(feature = "cli")]
+mod highlight;
 mod pratt;
 mod prec;
+mod scope;
+mod stmt;
+
+#[cfg(all(test, feature = "cli"))]
+mod tests;
+
+type Result<T> = std::result::Result<T, Diagnostic>;
 
-pub fn compile(src: String) -> anyhow::Result<Chunk> {
+/// Compiles the source code into a function object for the top-level script.
+/// `roots` are the caller's garbage collection roots, which need to be kept alive
+/// along with the compiler's own if a collection is triggered during compilation.
+///
+/// Compilation carries on past any errors, so that a single `Error::Compile`
+/// reports every problem in the script.
+pub fn compile(
+	src: String,
+	heap: &mut Heap,
+	roots: &dyn Trace,
+) -> std::result::Result<Obj, Error> {
 	debug::write_header("chunk");
 
-	let mut stream = Stream::from(src);
-	let mut chunk = stream.parse::<Chunk>()?;
+	let mut stream = Stream::from(lexer::strip_comments(&src));
+	let mut compiler = Compiler::new(heap, roots, src.into());
 
-	let (src, _) = stream.into_inner();
-	chunk.set_source(src);
+	let script = compiler.program(&mut stream);
 
 	debug::flush();
 
-	Ok(chunk)
+	if compiler
+		.diagnostics
+		.iter()
+		.any(Diagnostic::is_error)
+	{
+		Err(Error::Compile(compiler.diagnostics))
+	} else {
+		Ok(script)
+	}
 }
 
-impl<'a> Parse<'a> for Chunk
-where 'a: 'static
-{
-	type Stream = Stream<'a>;
+/// Parser state for a single compilation. Bytecode is written to the chunk of the
+/// innermost function being compiled, while any objects created along the way
+/// (e.g. string literals) are allocated on the VM's `heap`.
+struct Compiler<'a, 'h> {
+	heap: &'h mut Heap,
+	roots: &'h dyn Trace,
+	/// The source code being compiled, which is shared with every function's chunk
+	source: Rc<str>,
+	/// Whether the expression currently being parsed is allowed to be the target of
+	/// an assignment, i.e. whether it was parsed at `Prec::Assignment` or lower.
+	can_assign: bool,
+	/// The functions currently being compiled, from the top-level script down to the
+	/// innermost function declaration.
+	functions: Vec<FnState<'a>>,
+	/// The class declarations enclosing the code being compiled, from outermost to
+	/// innermost.
+	classes: Vec<ClassState>,
+	/// Problems found so far, in source order
+	diagnostics: Vec<Diagnostic>,
+}
+
+/// The compiler's nesting when a declaration started, to return to if the
+/// declaration turns out to have an error in it.
+#[derive(Clone, Copy)]
+struct Checkpoint {
+	functions: usize,
+	classes: usize,
+	locals: usize,
+	scope_depth: usize,
+}
+
+/// Compiler state for a single function body.
+struct FnState<'a> {
+	kind: FnKind,
+	name: Option<Obj>,
+	arity: u8,
+	chunk: Chunk,
+	/// Local variables currently in scope, in the same order as the stack slots
+	/// they'll occupy at runtime.
+	locals: Vec<Local<'a>>,
+	upvalues: Vec<Upvalue>,
+	scope_depth: usize,
+}
+
+/// Compiler state for a single class body.
+struct ClassState {
+	/// Whether the class has a superclass, and so whether `super` can be used in its
+	/// methods.
+	has_superclass: bool,
+}
+
+impl<'a> Trace for FnState<'a> {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.name.trace(tracer);
+		self.chunk.trace(tracer);
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FnKind {
+	Script,
+	Function,
+	Method,
+	Initializer,
+}
+
+impl<'a> FnState<'a> {
+	fn new(kind: FnKind, name: Option<Obj>, source: Rc<str>) -> Self {
+		let mut locals = Vec::with_capacity(scope::MAX_LOCALS);
+		// The first stack slot of every call frame is reserved for the function
+		// being called, or the receiver of a method call
+		locals.push(match kind {
+			FnKind::Method | FnKind::Initializer => Local::reserved("this"),
+			FnKind::Script | FnKind::Function => Local::reserved(""),
+		});
 
-	fn parse(input: &mut Self::Stream) -> Result<'a, Self> {
 		let mut chunk = Chunk::new();
+		chunk.set_source(source);
+
+		Self {
+			kind,
+			name,
+			arity: 0,
+			chunk,
+			locals,
+			upvalues: vec![],
+			scope_depth: 0,
+		}
+	}
+}
+
+impl<'a, 'h> Compiler<'a, 'h>
+where 'a: 'static
+{
+	fn new(heap: &'h mut Heap, roots: &'h dyn Trace, source: Rc<str>) -> Self {
+		Self {
+			heap,
+			roots,
+			source: source.clone(),
+			can_assign: false,
+			functions: vec![FnState::new(FnKind::Script, None, source)],
+			classes: vec![],
+			diagnostics: vec![],
+		}
+	}
 
+	fn program(&mut self, input: &mut Stream<'a>) -> Obj {
 		while !input.is_empty() {
-			chunk.expression(input)?;
+			self.declaration(input);
 		}
 
-		Ok(chunk)
+		// Implicit return at the end of the script
+		let span = input
+			.prev()
+			.map_or_else(Span::default, |token| token.span());
+
+		let (script, _) = self.end_function(span);
+
+		script
+	}
+
+	fn checkpoint(&self) -> Checkpoint {
+		Checkpoint {
+			functions: self.functions.len(),
+			classes: self.classes.len(),
+			locals: self.current().locals.len(),
+			scope_depth: self.current().scope_depth,
+		}
+	}
+
+	/// Discards any functions, classes and scopes that were entered since the
+	/// checkpoint without being finished. The bytecode is left as-is, since it won't
+	/// be run anyway.
+	fn restore(&mut self, checkpoint: Checkpoint) {
+		self.functions.truncate(checkpoint.functions);
+		self.classes.truncate(checkpoint.classes);
+
+		let function = self.current_mut();
+		function.locals.truncate(checkpoint.locals);
+		function.scope_depth = checkpoint.scope_depth;
+	}
+
+	/// Skips ahead to the start of the next statement after an error, so that one
+	/// mistake doesn't set off a cascade of others.
+	fn synchronize(&mut self, input: &mut Stream<'a>) {
+		while let Some(token) = input.peek() {
+			if let Token::Keyword(
+				"class" | "fun" | "var" | "for" | "if" | "while" | "print" | "return",
+				_,
+			) = token
+			{
+				return;
+			}
+			if let Some(Token::Punct(";", _)) = input.next() {
+				return;
+			}
+		}
+	}
+
+	fn begin_function(&mut self, kind: FnKind, name: &str) {
+		let name = self.intern(name);
+		let source = self.source.clone();
+		self.functions
+			.push(FnState::new(kind, Some(name), source));
+	}
+
+	/// Emits the function's implicit `return` and allocates the finished function
+	/// object. Also returns the variables captured by the function, which the
+	/// enclosing function emits as operands of its `Closure` instruction.
+	fn end_function(&mut self, span: Span) -> (Obj, Vec<Upvalue>) {
+		// Initializers always return the instance being initialized
+		if self.current().kind == FnKind::Initializer {
+			self.chunk()
+				.extend(&[OpCode::GetLocal as u8, 0], span);
+		} else {
+			self.chunk().write_instr(OpCode::Nil, span);
+		}
+		self.chunk().write_instr(OpCode::Return, span);
+
+		let FnState {
+			name,
+			arity,
+			chunk,
+			upvalues,
+			..
+		} = self.functions.pop().unwrap();
+
+		let function = ObjFunction::new(arity, upvalues.len() as u8, chunk, name);
+
+		(self.alloc(function), upvalues)
+	}
+
+	/// Allocates a new object on the heap, collecting garbage first if needed. Along
+	/// with the caller's roots, the objects referenced by every function being
+	/// compiled (and by the new object itself) are kept alive.
+	fn alloc<T: Object>(&mut self, object: Box<T>) -> Obj {
+		let roots = (self.roots, &self.functions[..], &*object);
+		self.heap.collect_if_needed(&roots);
+		self.heap.alloc(object)
+	}
+
+	fn intern(&mut self, chars: &str) -> Obj {
+		let roots = (self.roots, &self.functions[..]);
+		self.heap.collect_if_needed(&roots);
+		self.heap.intern(chars)
+	}
+
+	/// The index of the innermost function being compiled in `self.functions`
+	fn current_idx(&self) -> usize {
+		self.functions.len() - 1
+	}
+
+	fn current(&self) -> &FnState<'a> {
+		self.functions.last().unwrap()
+	}
+
+	fn current_mut(&mut self) -> &mut FnState<'a> {
+		self.functions.last_mut().unwrap()
+	}
+
+	fn chunk(&mut self) -> &mut Chunk {
+		&mut self.current_mut().chunk
 	}
-}
 
-impl Chunk {
 	#[trace(debug::codegen_instr)]
 	fn emit_instr(&mut self, op: OpCode, span: Span) {
-		self.write_instr(op, span.start.line + 1);
+		self.chunk().write_instr(op, span);
 	}
 
 	#[trace(debug::codegen_pair)]
 	fn emit_pair(&mut self, pair: (OpCode, OpCode), span: Span) {
 		let (a, b) = pair;
-		self.extend(&[a as u8, b as u8], span.start.line + 1);
+		self.chunk().extend(&[a as u8, b as u8], span);
+	}
+
+	#[trace(debug::codegen_operand)]
+	fn emit_operand(&mut self, op: OpCode, operand: u8, span: Span) {
+		self.chunk().extend(&[op as u8, operand], span);
+	}
+
+	// Emits a jump with a placeholder operand, returning the operand's offset so it
+	// can be back-patched once the jump's destination is known
+	#[trace(debug::codegen_instr)]
+	fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+		self.chunk().write_jump(op, span)
+	}
+
+	fn patch_jump(
+		&mut self,
+		offset: usize,
+		span: Span,
+		input: &Stream<'a>,
+	) -> Result<()> {
+		self.chunk()
+			.patch_jump(offset)
+			.map_err(|JumpError(message)| {
+				Diagnostic::error(Code::Limit, message, input.source(), Some(span))
+			})
+	}
+
+	#[trace(debug::codegen_loop)]
+	fn emit_loop(
+		&mut self,
+		loop_start: usize,
+		span: Span,
+		input: &Stream<'a>,
+	) -> Result<()> {
+		self.chunk()
+			.write_loop(loop_start, span)
+			.map_err(|JumpError(message)| {
+				Diagnostic::error(Code::Limit, message, input.source(), Some(span))
+			})
 	}
 
 	#[trace(debug::codegen_const)]
 	fn emit_const(&mut self, value: Value, span: Span) {
-		self.write_const(value, span.start.line + 1);
+		self.chunk().write_const(value, span);
+	}
+
+	// Emits an instruction whose operand is an index into the constant pool, widening
+	// the instruction as needed to fit the index
+	#[trace(debug::codegen_indexed)]
+	fn emit_indexed(&mut self, op: OpCode, handle: usize, span: Span) {
+		self.chunk().write_indexed(op, handle, span);
+	}
+
+	// Emits an `Invoke` or `SuperInvoke` instruction, which is followed by the
+	// argument count in addition to the method name's index in the constant pool
+	#[trace(debug::codegen_invoke)]
+	fn emit_invoke(&mut self, op: OpCode, handle: usize, argc: u8, span: Span) {
+		self.chunk().write_indexed(op, handle, span);
+		self.chunk().extend(&[argc], span);
+	}
+
+	#[trace(debug::codegen_closure)]
+	fn emit_closure(&mut self, function: Obj, upvalues: &[Upvalue], span: Span) {
+		let handle = self.chunk().add_constant(Value::Obj(function));
+
+		self.chunk()
+			.write_indexed(OpCode::Closure, handle, span);
+		for upvalue in upvalues {
+			self.chunk()
+				.extend(&[upvalue.is_local as u8, upvalue.index], span);
+		}
+	}
+
+	/// Interns the identifier's name and adds it to the constant pool, so that
+	/// global variable instructions can refer to it by index.
+	fn identifier_constant(&mut self, name: &str) -> usize {
+		let name = self.intern(name);
+		self.chunk().add_constant(Value::Obj(name))
 	}
 }
 
 #[rustfmt::skip]
-#[cfg(not(debug_assertions))]
+#[cfg(not(all(debug_assertions, feature = "cli")))]
 mod debug {
 	use gramatika::Span;
 
 	use super::{
 		chunk::OpCode,
-		lexer::{Stream, Token},
+		lexer::Stream,
 		pratt::HashToken,
 		prec::Prec,
-		repr::Value,
+		repr::{Obj, Value},
+		scope::Upvalue,
 	};
 
 	pub enum RuleType {
@@ -95,6 +414,12 @@ mod debug {
 	#[inline(always)] pub(super) fn set_rule_type(_: RuleType) {}
 	#[inline(always)] pub(super) fn parse_fn(_: &'static str, _: &mut Stream) {}
 	#[inline(always)] pub(super) fn codegen_instr(_: &'static str, _: OpCode, _: Span) {}
+	#[inline(always)] pub(super) fn codegen_pair(_: &'static str, _: (OpCode, OpCode), _: Span) {}
+	#[inline(always)] pub(super) fn codegen_operand(_: &'static str, _: OpCode, _: u8, _: Span) {}
+	#[inline(always)] pub(super) fn codegen_loop(_: &'static str, _: usize, _: Span, _: &Stream) {}
 	#[inline(always)] pub(super) fn codegen_const(_: &'static str, _: Value, _: Span) {}
+	#[inline(always)] pub(super) fn codegen_invoke(_: &'static str, _: OpCode, _: usize, _: u8, _: Span) {}
+	#[inline(always)] pub(super) fn codegen_closure(_: &'static str, _: Obj, _: &[Upvalue], _: Span) {}
+	#[inline(always)] pub(super) fn codegen_indexed(_: &'static str, _: OpCode, _: usize, _: Span) {}
 	#[inline(always)] pub(super) fn flush() {}
 }
@@ -1,70 +1,92 @@
 use std::hash::BuildHasherDefault;
 
-use gramatika::{ParseStreamer, Result, Spanned, SpannedError};
+use gramatika::{ParseStreamer, Spanned};
 use macro_utils::trace;
 use once_cell::sync::OnceCell;
 use rustc_hash::{FxHashMap, FxHasher};
 
 use crate::{
-	chunk::{Chunk, OpCode},
+	chunk::OpCode,
 	repr::Value,
+	vm::{Code, Diagnostic},
 	*,
 };
 
 use super::{
 	debug::{self, RuleType},
-	lexer::{Stream, Token},
+	lexer::{Stream, Token, TokenKind},
 	prec::Prec,
+	stmt::StmtParser,
+	Compiler, FnKind, Result,
 };
 
 static RULES: OnceCell<FxHashMap<HashToken, ParseRule>> = OnceCell::new();
 
+type ParseFn<'a> =
+	for<'c, 'h> fn(&'c mut Compiler<'a, 'h>, &'c mut Stream<'a>) -> Result<()>;
+
 pub(super) trait PrattParser<'a>
 where 'static: 'a
 {
-	type ParseFn;
-
-	fn expression(&mut self, input: &mut Stream<'a>) -> Result<'a, ()>;
-	fn parse_precedence(&mut self, input: &mut Stream<'a>, prec: Prec) -> Result<'a, ()>;
-	fn number(&mut self, input: &mut Stream<'a>) -> Result<'a, ()>;
-	fn unary(&mut self, input: &mut Stream<'a>) -> Result<'a, ()>;
-	fn binary(&mut self, input: &mut Stream<'a>) -> Result<'a, ()>;
-	fn literal(&mut self, input: &mut Stream<'a>) -> Result<'a, ()>;
-	fn grouping(&mut self, input: &mut Stream<'a>) -> Result<'a, ()>;
+	fn expression(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn parse_precedence(&mut self, input: &mut Stream<'a>, prec: Prec) -> Result<()>;
+	fn number(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn string(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn variable(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn named_variable(
+		&mut self,
+		name: Token<'a>,
+		can_assign: bool,
+		input: &mut Stream<'a>,
+	) -> Result<()>;
+	fn this(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn super_(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn unary(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn binary(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn call(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn argument_list(&mut self, input: &mut Stream<'a>) -> Result<u8>;
+	fn dot(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn literal(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn and(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn or(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn lambda(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn grouping(&mut self, input: &mut Stream<'a>) -> Result<()>;
 }
 
-impl<'a> PrattParser<'a> for Chunk
+impl<'a, 'h> PrattParser<'a> for Compiler<'a, 'h>
 where 'a: 'static
 {
-	type ParseFn = fn(&mut Self, input: &mut Stream<'a>) -> Result<'a, ()>;
-
 	#[trace(debug::entry)]
-	fn expression(&mut self, input: &mut Stream<'a>) -> Result<'a, ()> {
+	fn expression(&mut self, input: &mut Stream<'a>) -> Result<()> {
 		self.parse_precedence(input, Prec::Assignment)
 	}
 
 	#[trace(debug::precedence)]
-	fn parse_precedence(&mut self, input: &mut Stream<'a>, prec: Prec) -> Result<'a, ()> {
+	fn parse_precedence(&mut self, input: &mut Stream<'a>, prec: Prec) -> Result<()> {
 		if input.is_empty() {
-			return Err(SpannedError {
-				message: "Expected expression.".into(),
-				source: input.source(),
-				span: input.prev().map(|token| token.span()),
-			});
+			return Err(Diagnostic::error(
+				Code::Syntax,
+				"Expected expression.",
+				input.source(),
+				input.prev().map(|token| token.span()),
+			));
 		} else {
 			input.next().unwrap();
 		}
 
 		let prev = *input.prev().unwrap();
 		let rule = get_rule(prev.into());
+		let can_assign = prec <= Prec::Assignment;
 
 		debug::set_rule_type(RuleType::Prefix);
+		self.can_assign = can_assign;
 		match rule.prefix {
-			None => Err(SpannedError {
-				message: "Expected expression.".into(),
-				source: input.source(),
-				span: Some(prev.span()),
-			}),
+			None => Err(Diagnostic::error(
+				Code::Syntax,
+				"Expected expression.",
+				input.source(),
+				Some(prev.span()),
+			)),
 			Some(prefix_rule) => prefix_rule(self, input),
 		}?;
 
@@ -76,12 +98,14 @@ where 'a: 'static
 				input.next().unwrap();
 
 				debug::set_rule_type(RuleType::Infix);
+				self.can_assign = can_assign;
 				match rule.infix {
-					None => Err(SpannedError {
-						message: "Expected expression.".into(),
-						source: input.source(),
-						span: Some(prev.span()),
-					}),
+					None => Err(Diagnostic::error(
+						Code::Syntax,
+						"Expected expression.",
+						input.source(),
+						Some(prev.span()),
+					)),
 					Some(infix_rule) => infix_rule(self, input),
 				}?;
 			} else {
@@ -89,21 +113,29 @@ where 'a: 'static
 			}
 		}
 
+		// If the `=` wasn't consumed by one of the rules above, whatever's on its
+		// left-hand side can't be assigned to
+		if can_assign && input.check(operator!["="]) {
+			let token = input.next().unwrap();
+			return Err(Diagnostic::error(
+				Code::InvalidAssignment,
+				"Invalid assignment target.",
+				input.source(),
+				Some(token.span()),
+			));
+		}
+
 		Ok(())
 	}
 
 	#[trace(debug::parse_fn)]
-	fn number(&mut self, input: &mut Stream<'a>) -> Result<'a, ()> {
+	fn number(&mut self, input: &mut Stream<'a>) -> Result<()> {
 		let token = input.prev().unwrap();
 		let (lexeme, span) = token.as_inner();
 
-		let value = lexeme
-			.parse::<Value>()
-			.map_err(|err| SpannedError {
-				message: format!("{}", err),
-				source: input.source(),
-				span: Some(span),
-			})?;
+		let value = lexeme.parse::<Value>().map_err(|err| {
+			Diagnostic::error(Code::Syntax, err.to_string(), input.source(), Some(span))
+		})?;
 
 		self.emit_const(value, span);
 
@@ -111,7 +143,132 @@ where 'a: 'static
 	}
 
 	#[trace(debug::parse_fn)]
-	fn grouping(&mut self, input: &mut Stream<'a>) -> Result<'a, ()> {
+	fn string(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let token = input.prev().unwrap();
+		let (lexeme, span) = token.as_inner();
+
+		// Trim the surrounding quotes
+		let chars = &lexeme[1..lexeme.len() - 1];
+		let value = Value::Obj(self.intern(chars));
+
+		self.emit_const(value, span);
+
+		Ok(())
+	}
+
+	#[trace(debug::parse_fn)]
+	fn variable(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let name = *input.prev().unwrap();
+		self.named_variable(name, self.can_assign, input)
+	}
+
+	fn named_variable(
+		&mut self,
+		name: Token<'a>,
+		can_assign: bool,
+		input: &mut Stream<'a>,
+	) -> Result<()> {
+		let (name, span) = name.as_inner();
+		let current = self.current_idx();
+
+		let (get_op, set_op, operand) =
+			if let Some(slot) = self.resolve_local(current, name, span, input)? {
+				(OpCode::GetLocal, OpCode::SetLocal, Some(slot))
+			} else if let Some(idx) = self.resolve_upvalue(current, name, span, input)? {
+				(OpCode::GetUpvalue, OpCode::SetUpvalue, Some(idx))
+			} else {
+				(OpCode::GetGlobal, OpCode::SetGlobal, None)
+			};
+
+		let assign = can_assign && input.check(operator!["="]);
+		if assign {
+			input.consume(operator!["="])?;
+			self.expression(input)?;
+		}
+
+		match (operand, assign) {
+			(Some(operand), false) => self.emit_operand(get_op, operand, span),
+			(Some(operand), true) => self.emit_operand(set_op, operand, span),
+			(None, assign) => {
+				let handle = self.identifier_constant(name);
+				let op = if assign {
+					OpCode::SetGlobal
+				} else {
+					OpCode::GetGlobal
+				};
+				self.emit_indexed(op, handle, span);
+			}
+		}
+
+		Ok(())
+	}
+
+	#[trace(debug::parse_fn)]
+	fn this(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = *input.prev().unwrap();
+
+		// Nested functions can still refer to the `this` of an enclosing method
+		let in_method = self.functions.iter().any(|function| {
+			matches!(function.kind, FnKind::Method | FnKind::Initializer)
+		});
+
+		if !in_method {
+			return Err(Diagnostic::error(
+				Code::InvalidContext,
+				"Can't use `this` outside of a method.",
+				input.source(),
+				Some(keyword.span()),
+			));
+		}
+
+		// `this` is never assignable
+		self.named_variable(keyword, false, input)
+	}
+
+	#[trace(debug::parse_fn)]
+	fn super_(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = *input.prev().unwrap();
+		let message = match self.classes.last() {
+			None => Some("Can't use `super` outside of a class."),
+			Some(class) if !class.has_superclass => {
+				Some("Can't use `super` in a class with no superclass.")
+			}
+			Some(_) => None,
+		};
+		if let Some(message) = message {
+			return Err(Diagnostic::error(
+				Code::InvalidContext,
+				message,
+				input.source(),
+				Some(keyword.span()),
+			));
+		}
+
+		input.consume(punct!["."])?;
+		let (name, span) = input.consume_kind(TokenKind::Ident)?.as_inner();
+		let handle = self.identifier_constant(name);
+
+		// `this` and `super` are both locals (or upvalues) in every method of a
+		// subclass, so they can be resolved just like any other variable
+		let this = Token::Keyword("this", keyword.span());
+		let super_ = Token::Keyword("super", keyword.span());
+
+		self.named_variable(this, false, input)?;
+		if input.check(brace!["("]) {
+			input.consume(brace!["("])?;
+			let argc = self.argument_list(input)?;
+			self.named_variable(super_, false, input)?;
+			self.emit_invoke(OpCode::SuperInvoke, handle, argc, span);
+		} else {
+			self.named_variable(super_, false, input)?;
+			self.emit_indexed(OpCode::GetSuper, handle, span);
+		}
+
+		Ok(())
+	}
+
+	#[trace(debug::parse_fn)]
+	fn grouping(&mut self, input: &mut Stream<'a>) -> Result<()> {
 		self.expression(input)?;
 		input.consume(brace![")"])?;
 
@@ -119,7 +276,7 @@ where 'a: 'static
 	}
 
 	#[trace(debug::parse_fn)]
-	fn unary(&mut self, input: &mut Stream<'a>) -> Result<'a, ()> {
+	fn unary(&mut self, input: &mut Stream<'a>) -> Result<()> {
 		let prev = *input.prev().unwrap();
 
 		// Handle the operand
@@ -129,11 +286,12 @@ where 'a: 'static
 			Token::Operator("-", span) => self.emit_instr(OpCode::Negate, span),
 			Token::Operator("!", span) => self.emit_instr(OpCode::Not, span),
 			other => {
-				return Err(SpannedError {
-					message: "Expected `-` or `!`".into(),
-					span: Some(other.span()),
-					source: input.source(),
-				})
+				return Err(Diagnostic::error(
+					Code::Syntax,
+					"Expected `-` or `!`",
+					input.source(),
+					Some(other.span()),
+				))
 			}
 		}
 
@@ -141,7 +299,7 @@ where 'a: 'static
 	}
 
 	#[trace(debug::parse_fn)]
-	fn binary(&mut self, input: &mut Stream<'a>) -> Result<'a, ()> {
+	fn binary(&mut self, input: &mut Stream<'a>) -> Result<()> {
 		use OpCode::*;
 		use Token::*;
 
@@ -169,7 +327,70 @@ where 'a: 'static
 	}
 
 	#[trace(debug::parse_fn)]
-	fn literal(&mut self, input: &mut Stream<'a>) -> Result<'a, ()> {
+	fn call(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let argc = self.argument_list(input)?;
+		let paren = input.prev().unwrap().span();
+
+		self.emit_operand(OpCode::Call, argc, paren);
+
+		Ok(())
+	}
+
+	// Compiles a parenthesized argument list whose opening `(` was just consumed,
+	// returning the number of arguments
+	fn argument_list(&mut self, input: &mut Stream<'a>) -> Result<u8> {
+		let mut argc = 0_u8;
+
+		if !input.check(brace![")"]) {
+			loop {
+				self.expression(input)?;
+				if argc == u8::MAX {
+					return Err(Diagnostic::error(
+						Code::Limit,
+						"Can't have more than 255 arguments.",
+						input.source(),
+						input.prev().map(|token| token.span()),
+					));
+				}
+				argc += 1;
+
+				if input.check(punct![","]) {
+					input.consume(punct![","])?;
+				} else {
+					break;
+				}
+			}
+		}
+		input.consume(brace![")"])?;
+
+		Ok(argc)
+	}
+
+	#[trace(debug::parse_fn)]
+	fn dot(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let can_assign = self.can_assign;
+		let (name, span) = input.consume_kind(TokenKind::Ident)?.as_inner();
+		let handle = self.identifier_constant(name);
+
+		if can_assign && input.check(operator!["="]) {
+			input.consume(operator!["="])?;
+			self.expression(input)?;
+			self.emit_indexed(OpCode::SetProperty, handle, span);
+		} else if input.check(brace!["("]) {
+			// Method calls are compiled to a single `Invoke` instead of a
+			// `GetProperty` followed by a `Call`
+			input.consume(brace!["("])?;
+			let argc = self.argument_list(input)?;
+			self.emit_invoke(OpCode::Invoke, handle, argc, span);
+		} else {
+			self.emit_indexed(OpCode::GetProperty, handle, span);
+		}
+
+		Ok(())
+	}
+
+	#[trace(debug::parse_fn)]
+	fn literal(&mut self, input: &mut Stream<'a>) -> Result<()> {
 		let (op, span) = match *input.prev().unwrap() {
 			Token::Keyword("true", span) => (OpCode::True, span),
 			Token::Keyword("false", span) => (OpCode::False, span),
@@ -180,6 +401,40 @@ where 'a: 'static
 
 		Ok(())
 	}
+
+	#[trace(debug::parse_fn)]
+	fn and(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let span = input.prev().unwrap().span();
+
+		// If the left-hand side is falsy, skip the right-hand side and leave it on the
+		// stack as the result
+		let end_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+		self.emit_instr(OpCode::Pop, span);
+		self.parse_precedence(input, Prec::And)?;
+
+		self.patch_jump(end_jump, span, input)
+	}
+
+	#[trace(debug::parse_fn)]
+	fn or(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let span = input.prev().unwrap().span();
+
+		// If the left-hand side is truthy, skip the right-hand side and leave it on
+		// the stack as the result
+		let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+		let end_jump = self.emit_jump(OpCode::Jump, span);
+
+		self.patch_jump(else_jump, span, input)?;
+		self.emit_instr(OpCode::Pop, span);
+		self.parse_precedence(input, Prec::Or)?;
+
+		self.patch_jump(end_jump, span, input)
+	}
+
+	#[trace(debug::parse_fn)]
+	fn lambda(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		self.function(FnKind::Function, None, input)
+	}
 }
 
 #[repr(u8)]
@@ -192,9 +447,17 @@ pub enum HashToken {
 	Factor,
 	Bang,
 	Number,
+	String,
+	Ident,
 	Literal,
 	Equality,
 	Comparison,
+	And,
+	Or,
+	Fun,
+	Dot,
+	This,
+	Super,
 }
 
 impl<'a> From<Token<'a>> for HashToken {
@@ -208,7 +471,15 @@ impl<'a> From<Token<'a>> for HashToken {
 			Token::Operator("==" | "!=", _) => Self::Equality,
 			Token::Operator("<" | "<=" | ">" | ">=", _) => Self::Comparison,
 			Token::NumLit(_, _) => Self::Number,
+			Token::StrLit(_, _) => Self::String,
+			Token::Ident(_, _) => Self::Ident,
 			Token::Keyword("true" | "false" | "nil", _) => Self::Literal,
+			Token::Keyword("and", _) => Self::And,
+			Token::Keyword("or", _) => Self::Or,
+			Token::Keyword("fun", _) => Self::Fun,
+			Token::Keyword("this", _) => Self::This,
+			Token::Keyword("super", _) => Self::Super,
+			Token::Punct(".", _) => Self::Dot,
 			_ => Self::None,
 		}
 	}
@@ -227,14 +498,14 @@ macro_rules! parse_fn {
 		None
 	};
 	($fn:ident) => {
-		Some(<Chunk as PrattParser>::$fn)
+		Some(|compiler, input| <Compiler as PrattParser>::$fn(compiler, input))
 	};
 }
 
 macro_rules! pratt_table {
 	($($key:ident => { $prefix:ident, $infix:ident, $prec:ident })+) => {{
 		let mut table: FxHashMap<HashToken, ParseRule> = FxHashMap::with_capacity_and_hasher(
-			6,
+			18,
 			BuildHasherDefault::<FxHasher>::default(),
 		);
 		$(table.insert(HashToken::$key, ParseRule {
@@ -251,8 +522,8 @@ macro_rules! pratt_table {
 pub struct ParseRule<'a>
 where 'a: 'static
 {
-	prefix: Option<<Chunk as PrattParser<'a>>::ParseFn>,
-	infix: Option<<Chunk as PrattParser<'a>>::ParseFn>,
+	prefix: Option<ParseFn<'a>>,
+	infix: Option<ParseFn<'a>>,
 	prec: Prec,
 }
 
@@ -260,15 +531,23 @@ fn init_pratt_table<'a>() -> FxHashMap<HashToken, ParseRule<'a>> {
 	pratt_table! {
 	// Token type      prefix     infix     precedence
 	// --------------------------------------------------
-		LeftParen  => { grouping,  None,     None }
+		LeftParen  => { grouping,  call,     Call }
+		Dot        => { None,      dot,      Call }
 		Minus      => { unary,     binary,   Term }
 		Plus       => { None,      binary,   Term }
 		Factor     => { None,      binary,   Factor }
 		Bang       => { unary,     None,     None }
 		Number     => { number,    None,     None }
+		String     => { string,    None,     None }
+		Ident      => { variable,  None,     None }
 		Literal    => { literal,   None,     None }
 		Equality   => { None,      binary,   Equality }
 		Comparison => { None,      binary,   Comparison }
+		And        => { None,      and,      And }
+		Or         => { None,      or,       Or }
+		Fun        => { lambda,    None,     None }
+		This       => { this,      None,     None }
+		Super      => { super_,    None,     None }
 		None       => { None,      None,     None }
 	}
 }
@@ -0,0 +1,251 @@
+use gramatika::Span;
+
+use crate::{
+	chunk::OpCode,
+	vm::{Code, Diagnostic},
+};
+
+use super::{
+	lexer::{Stream, Token},
+	Compiler, Result,
+};
+
+/// Local variable slots are addressed by a single-byte operand
+pub(super) const MAX_LOCALS: usize = u8::MAX as usize + 1;
+
+/// Likewise for upvalue indices
+pub(super) const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+
+pub(super) struct Local<'a> {
+	name: &'a str,
+	/// The depth of the scope the local was declared in, or `None` if its
+	/// initializer hasn't finished compiling yet.
+	depth: Option<usize>,
+	/// Whether the local is captured by a closure, in which case it needs to be
+	/// moved off the stack when it goes out of scope.
+	is_captured: bool,
+}
+
+/// A variable captured by the function being compiled, from the perspective of the
+/// enclosing function: either one of its locals, or one of its own upvalues.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) struct Upvalue {
+	pub index: u8,
+	pub is_local: bool,
+}
+
+impl<'a> Local<'a> {
+	/// The stack slot that holds the function being called. For methods, it holds
+	/// the receiver instead, which can be referred to as `this`; otherwise, `name`
+	/// is empty so that it can't be referred to at all.
+	pub(super) fn reserved(name: &'a str) -> Self {
+		Self {
+			name,
+			depth: Some(0),
+			is_captured: false,
+		}
+	}
+}
+
+impl<'a, 'h> Compiler<'a, 'h>
+where 'a: 'static
+{
+	pub(super) fn begin_scope(&mut self) {
+		self.current_mut().scope_depth += 1;
+	}
+
+	/// Closes the current scope, popping its locals off the stack. Locals captured
+	/// by a closure are moved into their upvalues instead.
+	pub(super) fn end_scope(&mut self, span: Span) {
+		let function = self.current_mut();
+		function.scope_depth -= 1;
+
+		let scope_depth = function.scope_depth;
+		let mut popped = vec![];
+		while let Some(local) = function.locals.last() {
+			if matches!(local.depth, Some(depth) if depth <= scope_depth) {
+				break;
+			}
+			popped.push(function.locals.pop().unwrap().is_captured);
+		}
+
+		for is_captured in popped {
+			if is_captured {
+				self.emit_instr(OpCode::CloseUpvalue, span);
+			} else {
+				self.emit_instr(OpCode::Pop, span);
+			}
+		}
+	}
+
+	/// Declares the variable named by `name` in the current scope. For globals,
+	/// returns the handle of the variable's name in the constant pool.
+	pub(super) fn declare_variable(
+		&mut self,
+		name: Token<'a>,
+		input: &Stream<'a>,
+	) -> Result<Option<usize>> {
+		let (name, span) = name.as_inner();
+		let function = self.current();
+
+		if function.scope_depth == 0 {
+			return Ok(Some(self.identifier_constant(name)));
+		}
+
+		let redeclared = function
+			.locals
+			.iter()
+			.rev()
+			.take_while(
+				|local| !matches!(local.depth, Some(depth) if depth < function.scope_depth),
+			)
+			.any(|local| local.name == name);
+
+		if redeclared {
+			return Err(Diagnostic::error(
+				Code::Redeclaration,
+				"Already a variable with this name in this scope.",
+				input.source(),
+				Some(span),
+			));
+		}
+
+		if function.locals.len() == MAX_LOCALS {
+			return Err(Diagnostic::error(
+				Code::Limit,
+				"Too many local variables in function.",
+				input.source(),
+				Some(span),
+			));
+		}
+
+		self.current_mut().locals.push(Local {
+			name,
+			depth: None,
+			is_captured: false,
+		});
+
+		Ok(None)
+	}
+
+	/// Makes a declared variable available for use once its initializer has been
+	/// compiled.
+	pub(super) fn define_variable(&mut self, global: Option<usize>, span: Span) {
+		match global {
+			Some(handle) => self.emit_indexed(OpCode::DefineGlobal, handle, span),
+			None => self.mark_initialized(),
+		}
+	}
+
+	/// Marks the most recently declared local as ready for use. Function
+	/// declarations do this before compiling their body, so they can refer to
+	/// themselves recursively.
+	pub(super) fn mark_initialized(&mut self) {
+		let function = self.current_mut();
+		if function.scope_depth == 0 {
+			return;
+		}
+
+		function.locals.last_mut().unwrap().depth = Some(function.scope_depth);
+	}
+
+	/// Returns the stack slot of the innermost local variable named `name` in the
+	/// function at index `function` of `self.functions`, or `None` if that function
+	/// has no such local.
+	pub(super) fn resolve_local(
+		&self,
+		function: usize,
+		name: &str,
+		span: Span,
+		input: &Stream<'a>,
+	) -> Result<Option<u8>> {
+		let found = self.functions[function]
+			.locals
+			.iter()
+			.enumerate()
+			.rev()
+			.find(|(_, local)| local.name == name);
+
+		match found {
+			Some((_, Local { depth: None, .. })) => Err(Diagnostic::error(
+				Code::SelfReference,
+				"Can't read local variable in its own initializer.",
+				input.source(),
+				Some(span),
+			)),
+			Some((slot, _)) => Ok(Some(slot as u8)),
+			None => Ok(None),
+		}
+	}
+
+	/// Looks for `name` in the functions enclosing the function at index `function`,
+	/// capturing it as an upvalue in each function in between. Returns the index
+	/// of the upvalue, or `None` if the variable isn't found (i.e., it's assumed to be
+	/// a global).
+	pub(super) fn resolve_upvalue(
+		&mut self,
+		function: usize,
+		name: &str,
+		span: Span,
+		input: &Stream<'a>,
+	) -> Result<Option<u8>> {
+		if function == 0 {
+			return Ok(None);
+		}
+
+		let enclosing = function - 1;
+		if let Some(slot) = self.resolve_local(enclosing, name, span, input)? {
+			self.functions[enclosing].locals[slot as usize].is_captured = true;
+
+			let upvalue = Upvalue {
+				index: slot,
+				is_local: true,
+			};
+			return self
+				.add_upvalue(function, upvalue, span, input)
+				.map(Some);
+		}
+
+		if let Some(index) = self.resolve_upvalue(enclosing, name, span, input)? {
+			let upvalue = Upvalue {
+				index,
+				is_local: false,
+			};
+			return self
+				.add_upvalue(function, upvalue, span, input)
+				.map(Some);
+		}
+
+		Ok(None)
+	}
+
+	fn add_upvalue(
+		&mut self,
+		function: usize,
+		upvalue: Upvalue,
+		span: Span,
+		input: &Stream<'a>,
+	) -> Result<u8> {
+		let upvalues = &mut self.functions[function].upvalues;
+
+		if let Some(idx) = upvalues
+			.iter()
+			.position(|&existing| existing == upvalue)
+		{
+			return Ok(idx as u8);
+		}
+
+		if upvalues.len() == MAX_UPVALUES {
+			return Err(Diagnostic::error(
+				Code::Limit,
+				"Too many closure variables in function.",
+				input.source(),
+				Some(span),
+			));
+		}
+
+		upvalues.push(upvalue);
+
+		Ok((upvalues.len() - 1) as u8)
+	}
+}
@@ -0,0 +1,426 @@
+use gramatika::{ParseStreamer, Spanned, Token as _};
+use macro_utils::trace;
+
+use crate::{
+	chunk::OpCode,
+	vm::{Code, Diagnostic},
+	*,
+};
+
+use super::{
+	debug,
+	lexer::{Stream, Token, TokenKind},
+	pratt::PrattParser,
+	ClassState, Compiler, FnKind, Result,
+};
+
+pub(super) trait StmtParser<'a>
+where 'static: 'a
+{
+	fn declaration(&mut self, input: &mut Stream<'a>);
+	fn class_declaration(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn method(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn var_declaration(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn fun_declaration(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn function(
+		&mut self,
+		kind: FnKind,
+		name: Option<Token<'a>>,
+		input: &mut Stream<'a>,
+	) -> Result<()>;
+	fn statement(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn block(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn if_statement(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn while_statement(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn for_statement(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn print_statement(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn return_statement(&mut self, input: &mut Stream<'a>) -> Result<()>;
+	fn expression_statement(&mut self, input: &mut Stream<'a>) -> Result<()>;
+}
+
+impl<'a, 'h> StmtParser<'a> for Compiler<'a, 'h>
+where 'a: 'static
+{
+	// Errors are reported here rather than propagated, so that the rest of the
+	// program still gets compiled and checked
+	#[trace(debug::entry)]
+	fn declaration(&mut self, input: &mut Stream<'a>) {
+		let checkpoint = self.checkpoint();
+		let result = if input.check(keyword!["class"]) {
+			self.class_declaration(input)
+		} else if input.check(keyword!["var"]) {
+			self.var_declaration(input)
+		} else if input.check(keyword!["fun"]) {
+			self.fun_declaration(input)
+		} else {
+			self.statement(input)
+		};
+
+		if let Err(diagnostic) = result {
+			self.diagnostics.push(diagnostic);
+			self.restore(checkpoint);
+			self.synchronize(input);
+		}
+	}
+
+	#[trace(debug::entry)]
+	fn class_declaration(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		input.consume(keyword!["class"])?;
+		let name = input.consume_kind(TokenKind::Ident)?;
+		let handle = self.identifier_constant(name.lexeme());
+		let global = self.declare_variable(name, input)?;
+
+		self.emit_indexed(OpCode::Class, handle, name.span());
+		self.define_variable(global, name.span());
+		self.classes.push(ClassState {
+			has_superclass: false,
+		});
+
+		if input.check(operator!["<"]) {
+			input.consume(operator!["<"])?;
+			let superclass = input.consume_kind(TokenKind::Ident)?;
+			if superclass.lexeme() == name.lexeme() {
+				return Err(Diagnostic::error(
+					Code::SelfReference,
+					"A class can't inherit from itself.",
+					input.source(),
+					Some(superclass.span()),
+				));
+			}
+			self.named_variable(superclass, false, input)?;
+
+			// Methods of the subclass capture the superclass as a local named `super`,
+			// in a scope surrounding the class body
+			self.begin_scope();
+			let super_ = Token::Keyword("super", superclass.span());
+			let local = self.declare_variable(super_, input)?;
+			self.define_variable(local, superclass.span());
+
+			self.named_variable(name, false, input)?;
+			self.emit_instr(OpCode::Inherit, superclass.span());
+			self.classes.last_mut().unwrap().has_superclass = true;
+		}
+
+		// Load the class back onto the stack so that methods can be bound to it
+		self.named_variable(name, false, input)?;
+
+		input.consume(brace!["{"])?;
+		while !input.is_empty() && !input.check(brace!["}"]) {
+			self.method(input)?;
+		}
+		let brace = input.consume(brace!["}"])?;
+
+		self.emit_instr(OpCode::Pop, brace.span());
+		if self.classes.pop().unwrap().has_superclass {
+			self.end_scope(brace.span());
+		}
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn method(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let name = input.consume_kind(TokenKind::Ident)?;
+		let handle = self.identifier_constant(name.lexeme());
+
+		let kind = if name.lexeme() == "init" {
+			FnKind::Initializer
+		} else {
+			FnKind::Method
+		};
+
+		self.function(kind, Some(name), input)?;
+		self.emit_indexed(OpCode::Method, handle, name.span());
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn var_declaration(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = input.consume(keyword!["var"])?;
+		let name = input.consume_kind(TokenKind::Ident)?;
+		let global = self.declare_variable(name, input)?;
+
+		if input.check(operator!["="]) {
+			input.consume(operator!["="])?;
+			self.expression(input)?;
+		} else {
+			self.emit_instr(OpCode::Nil, keyword.span());
+		}
+		input.consume(punct![";"])?;
+
+		self.define_variable(global, keyword.span());
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn fun_declaration(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = input.consume(keyword!["fun"])?;
+		let name = input.consume_kind(TokenKind::Ident)?;
+		let global = self.declare_variable(name, input)?;
+		self.mark_initialized();
+
+		self.function(FnKind::Function, Some(name), input)?;
+		self.define_variable(global, keyword.span());
+
+		Ok(())
+	}
+
+	/// Compiles a function's parameters and body into a new function object, and
+	/// emits a closure over it in the enclosing function. `name` is `None` for
+	/// anonymous function expressions, whose `fun` keyword was just consumed.
+	fn function(
+		&mut self,
+		kind: FnKind,
+		name: Option<Token<'a>>,
+		input: &mut Stream<'a>,
+	) -> Result<()> {
+		let span = match name {
+			Some(name) => {
+				self.begin_function(kind, name.lexeme());
+				name.span()
+			}
+			None => {
+				self.begin_function(kind, "(anonymous)");
+				input.prev().unwrap().span()
+			}
+		};
+		self.begin_scope();
+
+		input.consume(brace!["("])?;
+		if !input.check(brace![")"]) {
+			loop {
+				let param = input.consume_kind(TokenKind::Ident)?;
+				if self.current().arity == u8::MAX {
+					return Err(Diagnostic::error(
+						Code::Limit,
+						"Can't have more than 255 parameters.",
+						input.source(),
+						Some(param.span()),
+					));
+				}
+				self.current_mut().arity += 1;
+
+				let global = self.declare_variable(param, input)?;
+				self.define_variable(global, param.span());
+
+				if input.check(punct![","]) {
+					input.consume(punct![","])?;
+				} else {
+					break;
+				}
+			}
+		}
+		input.consume(brace![")"])?;
+
+		self.block(input)?;
+
+		let closing_brace = input.prev().unwrap().span();
+		let (function, upvalues) = self.end_function(closing_brace);
+		self.emit_closure(function, &upvalues, span);
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn statement(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		if input.check(keyword!["print"]) {
+			self.print_statement(input)
+		} else if input.check(keyword!["return"]) {
+			self.return_statement(input)
+		} else if input.check(keyword!["if"]) {
+			self.if_statement(input)
+		} else if input.check(keyword!["while"]) {
+			self.while_statement(input)
+		} else if input.check(keyword!["for"]) {
+			self.for_statement(input)
+		} else if input.check(brace!["{"]) {
+			self.begin_scope();
+			self.block(input)?;
+			self.end_scope(input.prev().unwrap().span());
+
+			Ok(())
+		} else {
+			self.expression_statement(input)
+		}
+	}
+
+	#[trace(debug::entry)]
+	fn block(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		input.consume(brace!["{"])?;
+		while !input.is_empty() && !input.check(brace!["}"]) {
+			self.declaration(input);
+		}
+		input.consume(brace!["}"])?;
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn if_statement(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = input.consume(keyword!["if"])?;
+		input.consume(brace!["("])?;
+		self.expression(input)?;
+		input.consume(brace![")"])?;
+
+		let then_jump = self.emit_jump(OpCode::JumpIfFalse, keyword.span());
+		self.emit_instr(OpCode::Pop, keyword.span());
+		self.statement(input)?;
+
+		let then_end = input.prev().unwrap().span();
+		let else_jump = self.emit_jump(OpCode::Jump, then_end);
+
+		self.patch_jump(then_jump, keyword.span(), input)?;
+		self.emit_instr(OpCode::Pop, then_end);
+
+		if input.check(keyword!["else"]) {
+			input.consume(keyword!["else"])?;
+			self.statement(input)?;
+		}
+		self.patch_jump(else_jump, then_end, input)?;
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn while_statement(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let loop_start = self.chunk().len();
+		let keyword = input.consume(keyword!["while"])?;
+		input.consume(brace!["("])?;
+		self.expression(input)?;
+		input.consume(brace![")"])?;
+
+		let exit_jump = self.emit_jump(OpCode::JumpIfFalse, keyword.span());
+		self.emit_instr(OpCode::Pop, keyword.span());
+		self.statement(input)?;
+
+		let body_end = input.prev().unwrap().span();
+		self.emit_loop(loop_start, body_end, input)?;
+
+		self.patch_jump(exit_jump, keyword.span(), input)?;
+		self.emit_instr(OpCode::Pop, body_end);
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn for_statement(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = input.consume(keyword!["for"])?;
+		self.begin_scope();
+		input.consume(brace!["("])?;
+
+		// Initializer
+		if input.check(punct![";"]) {
+			input.consume(punct![";"])?;
+		} else if input.check(keyword!["var"]) {
+			self.var_declaration(input)?;
+		} else {
+			self.expression_statement(input)?;
+		}
+
+		// Condition
+		let mut loop_start = self.chunk().len();
+		let exit_jump = if input.check(punct![";"]) {
+			input.consume(punct![";"])?;
+			None
+		} else {
+			self.expression(input)?;
+			let semicolon = input.consume(punct![";"])?;
+
+			let exit_jump = self.emit_jump(OpCode::JumpIfFalse, semicolon.span());
+			self.emit_instr(OpCode::Pop, semicolon.span());
+
+			Some(exit_jump)
+		};
+
+		// Increment -- compiled before the body, but executed after it, so we jump
+		// over it on the way into the body and loop back to it at the end
+		if input.check(brace![")"]) {
+			input.consume(brace![")"])?;
+		} else {
+			let body_jump = self.emit_jump(OpCode::Jump, keyword.span());
+			let increment_start = self.chunk().len();
+
+			self.expression(input)?;
+			let paren = input.consume(brace![")"])?;
+			self.emit_instr(OpCode::Pop, paren.span());
+
+			self.emit_loop(loop_start, paren.span(), input)?;
+			loop_start = increment_start;
+			self.patch_jump(body_jump, keyword.span(), input)?;
+		}
+
+		self.statement(input)?;
+
+		let body_end = input.prev().unwrap().span();
+		self.emit_loop(loop_start, body_end, input)?;
+
+		if let Some(exit_jump) = exit_jump {
+			self.patch_jump(exit_jump, keyword.span(), input)?;
+			self.emit_instr(OpCode::Pop, body_end);
+		}
+		self.end_scope(body_end);
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn print_statement(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = input.consume(keyword!["print"])?;
+		self.expression(input)?;
+		input.consume(punct![";"])?;
+
+		self.emit_instr(OpCode::Print, keyword.span());
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn return_statement(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		let keyword = input.consume(keyword!["return"])?;
+
+		if self.current().kind == FnKind::Script {
+			return Err(Diagnostic::error(
+				Code::InvalidContext,
+				"Can't return from top-level code.",
+				input.source(),
+				Some(keyword.span()),
+			));
+		}
+
+		if input.check(punct![";"]) {
+			input.consume(punct![";"])?;
+			if self.current().kind == FnKind::Initializer {
+				self.emit_operand(OpCode::GetLocal, 0, keyword.span());
+			} else {
+				self.emit_instr(OpCode::Nil, keyword.span());
+			}
+		} else {
+			if self.current().kind == FnKind::Initializer {
+				return Err(Diagnostic::error(
+					Code::InvalidContext,
+					"Can't return a value from an initializer.",
+					input.source(),
+					Some(keyword.span()),
+				));
+			}
+
+			self.expression(input)?;
+			input.consume(punct![";"])?;
+		}
+		self.emit_instr(OpCode::Return, keyword.span());
+
+		Ok(())
+	}
+
+	#[trace(debug::entry)]
+	fn expression_statement(&mut self, input: &mut Stream<'a>) -> Result<()> {
+		self.expression(input)?;
+		let semicolon = input.consume(punct![";"])?;
+
+		self.emit_instr(OpCode::Pop, semicolon.span());
+
+		Ok(())
+	}
+}
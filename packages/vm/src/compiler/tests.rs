@@ -0,0 +1,84 @@
+use nu_ansi_term::Color;
+
+use super::{highlight, is_incomplete};
+
+#[test]
+fn complete_input() {
+	for src in [
+		"",
+		"print 1 + 2;",
+		"fun f() { return 1; }",
+		"print (1 + 2) * 3;",
+		r#"print "(";"#,
+		"print 1; // {",
+		"print 1 + ;", // Still invalid, but there's nothing left to wait for
+		"}",
+	] {
+		assert!(!is_incomplete(src), "{:?} should be complete", src);
+	}
+}
+
+#[test]
+fn incomplete_input() {
+	for src in [
+		"fun f() {",
+		"class A {\n\tmethod() {\n\t}",
+		"print (1 +",
+		r#"print "unterminated"#,
+		"var a = 1 +",
+		"var a =",
+		"print a.",
+		"f(1,",
+		"print a and",
+		"print !",
+	] {
+		assert!(is_incomplete(src), "{:?} should be incomplete", src);
+	}
+}
+
+#[test]
+fn highlighting() {
+	assert_eq!(highlight("var x = 1; // one"), [format!(
+		"{} {} {} {}{} {}",
+		Color::Magenta.italic().paint("var"),
+		Color::LightBlue.paint("x"),
+		Color::LightGray.paint("="),
+		Color::Cyan.paint("1"),
+		Color::DarkGray.paint(";"),
+		Color::DarkGray.paint("// one"),
+	)]);
+
+	// Strings can span lines
+	assert_eq!(highlight("print \"a\nb\";"), [
+		format!(
+			"{} {}",
+			Color::Magenta.italic().paint("print"),
+			Color::Green.paint("\"a"),
+		),
+		format!(
+			"{}{}",
+			Color::Green.paint("b\""),
+			Color::DarkGray.paint(";")
+		),
+	]);
+}
+
+#[test]
+fn highlighting_errors() {
+	assert_eq!(highlight("1 @ nil"), [format!(
+		"{} {} {}",
+		Color::Cyan.paint("1"),
+		Color::Red.paint("@"),
+		Color::Cyan.italic().paint("nil"),
+	)]);
+
+	// Everything after an unterminated string is part of it
+	assert_eq!(highlight("print \"oops;\n}"), [
+		format!(
+			"{} {}",
+			Color::Magenta.italic().paint("print"),
+			Color::Red.paint("\"oops;"),
+		),
+		Color::Red.paint("}").to_string(),
+	]);
+}
@@ -4,7 +4,7 @@ use std::{
 };
 
 use crate::{
-	chunk::{JoinBytes, Lines, OpCode, OpCodeError},
+	chunk::{JoinBytes, OpCode, OpCodeError, SourceMap},
 	repr::Value,
 };
 
@@ -12,7 +12,7 @@ pub trait DebugInstruction {
 	fn debug_chunk<'a, I>(
 		&mut self,
 		bytes: &mut I,
-		lines: &Lines,
+		source_map: &SourceMap,
 		constants: &[Value],
 	) -> fmt::Result
 	where
@@ -21,14 +21,15 @@ pub trait DebugInstruction {
 	fn debug_next_instr<I: JoinBytes>(
 		&mut self,
 		bytes: &mut I,
-		lines: &Lines,
+		source_map: &SourceMap,
 		constants: &[Value],
 		offset: usize,
 		byte: u8,
 	) -> fmt::Result;
 
 	fn print_offset(&mut self, offset: usize) -> fmt::Result;
-	fn print_line_number(&mut self, lines: &Lines, offset: usize) -> fmt::Result;
+	fn print_line_number(&mut self, source_map: &SourceMap, offset: usize)
+		-> fmt::Result;
 	fn print_opcode(&mut self, op: OpCode) -> fmt::Result;
 	fn print_opcode_and_value(
 		&mut self,
@@ -36,20 +37,28 @@ pub trait DebugInstruction {
 		handle: usize,
 		value: Value,
 	) -> fmt::Result;
+	fn print_opcode_and_operand(&mut self, op: OpCode, operand: usize) -> fmt::Result;
+	fn print_jump(&mut self, op: OpCode, offset: usize, target: usize) -> fmt::Result;
+	fn print_upvalue(
+		&mut self,
+		offset: usize,
+		is_local: bool,
+		index: usize,
+	) -> fmt::Result;
 }
 
 impl<T: Write> DebugInstruction for T {
 	fn debug_chunk<'a, I>(
 		&mut self,
 		bytes: &mut I,
-		lines: &Lines,
+		source_map: &SourceMap,
 		constants: &[Value],
 	) -> fmt::Result
 	where
 		I: Iterator<Item = (usize, &'a u8)> + ExactSizeIterator + JoinBytes,
 	{
 		while let Some((offset, byte)) = bytes.next() {
-			self.debug_next_instr(bytes, lines, constants, offset, *byte)?;
+			self.debug_next_instr(bytes, source_map, constants, offset, *byte)?;
 
 			// Insert newline if this isn't the last instruction
 			if bytes.len() > 0 {
@@ -63,32 +72,66 @@ impl<T: Write> DebugInstruction for T {
 	fn debug_next_instr<I: JoinBytes>(
 		&mut self,
 		bytes: &mut I,
-		lines: &Lines,
+		source_map: &SourceMap,
 		constants: &[Value],
 		offset: usize,
 		byte: u8,
 	) -> fmt::Result {
 		self.print_offset(offset)?;
-		self.print_line_number(lines, offset)?;
+		self.print_line_number(source_map, offset)?;
 
 		// Print the OpCode
 		match OpCode::try_from(byte) {
-			// For constants, we also print the index of the value in the pool,
-			// followed by the value itself
-			Ok(
-				op @ OpCode::Constant | op @ OpCode::Constant16 | op @ OpCode::Constant24,
-			) => {
-				let handle = match op {
-					OpCode::Constant => bytes.join_bytes(1),
-					OpCode::Constant16 => bytes.join_bytes(2),
-					OpCode::Constant24 => bytes.join_bytes(3),
-					_ => unreachable!(),
-				}
-				.ok_or(fmt::Error)?;
+			// For instructions that reference the constant pool, we also print the
+			// index of the value in the pool, followed by the value itself
+			Ok(op) if op.const_width().is_some() => {
+				let handle = bytes
+					.join_bytes(op.const_width().unwrap())
+					.ok_or(fmt::Error)?;
 
 				let value = constants[handle];
 
-				self.print_opcode_and_value(op, handle, value)
+				self.print_opcode_and_value(op, handle, value)?;
+
+				// Closures are followed by a pair of bytes for each captured variable
+				if matches!(op, OpCode::Closure | OpCode::Closure16 | OpCode::Closure24) {
+					let upvalue_count = match value {
+						Value::Obj(obj) => {
+							obj.as_function().map_or(0, |f| f.upvalue_count())
+						}
+						_ => 0,
+					};
+
+					let mut offset = offset + 1 + op.const_width().unwrap();
+					for _ in 0..upvalue_count {
+						let is_local = bytes.join_bytes(1).ok_or(fmt::Error)? == 1;
+						let index = bytes.join_bytes(1).ok_or(fmt::Error)?;
+
+						writeln!(self)?;
+						self.print_upvalue(offset, is_local, index)?;
+						offset += 2;
+					}
+				}
+
+				// Method invocations are followed by their argument count
+				if let Some(width) = op.operand_width() {
+					let argc = bytes.join_bytes(width).ok_or(fmt::Error)?;
+					write!(self, " ({} args)", argc)?;
+				}
+
+				Ok(())
+			}
+			// For other instructions with an operand (e.g. a stack slot), we just
+			// print the operand itself
+			Ok(op) if op.operand_width().is_some() => {
+				let width = op.operand_width().unwrap();
+				let operand = bytes.join_bytes(width).ok_or(fmt::Error)?;
+
+				// Jumps are printed with their absolute destination
+				match op.jump_target(offset + 1 + width, operand) {
+					Some(target) => self.print_jump(op, offset, target),
+					None => self.print_opcode_and_operand(op, operand),
+				}
 			}
 			Ok(op) => self.print_opcode(op),
 			Err(OpCodeError(msg)) => write!(self, "<{}>", msg),
@@ -101,10 +144,14 @@ impl<T: Write> DebugInstruction for T {
 		write!(self, "{:04}  ", offset)
 	}
 
-	fn print_line_number(&mut self, lines: &Lines, offset: usize) -> fmt::Result {
-		let line = lines.find_line(offset);
+	fn print_line_number(
+		&mut self,
+		source_map: &SourceMap,
+		offset: usize,
+	) -> fmt::Result {
+		let line = source_map.find_line(offset);
 		let prev_line = if offset > 0 {
-			Some(lines.find_line(offset - 1))
+			Some(source_map.find_line(offset - 1))
 		} else {
 			None
 		};
@@ -128,6 +175,24 @@ impl<T: Write> DebugInstruction for T {
 	) -> fmt::Result {
 		write!(self, "{:<16?}  [{}] '{}'", op, handle, value)
 	}
+
+	fn print_opcode_and_operand(&mut self, op: OpCode, operand: usize) -> fmt::Result {
+		write!(self, "{:<16?}  {}", op, operand)
+	}
+
+	fn print_jump(&mut self, op: OpCode, offset: usize, target: usize) -> fmt::Result {
+		write!(self, "{:<16?}  {:04} -> {:04}", op, offset, target)
+	}
+
+	fn print_upvalue(
+		&mut self,
+		offset: usize,
+		is_local: bool,
+		index: usize,
+	) -> fmt::Result {
+		let kind = if is_local { "local" } else { "upvalue" };
+		write!(self, "{:04}     | {:<16}  {} {}", offset, "", kind, index)
+	}
 }
 
 pub fn print_aligned(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
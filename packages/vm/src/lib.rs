@@ -0,0 +1,28 @@
+#![feature(allocator_api)]
+#![feature(stdio_locked)]
+
+#[macro_use]
+extern crate gramatika;
+extern crate lazy_static;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod repr;
+pub mod vm;
+
+mod chunk;
+mod compiler;
+mod debug;
+mod stack;
+mod table;
+mod vector;
+
+pub use macro_utils::{native, FromLox, IntoLox};
+
+pub use crate::{
+	repr::{Userdata, Value},
+	vm::{
+		ConversionError, Error, FromLox, IntoLox, Native, NativeFn, Options,
+		RuntimeError, VM,
+	},
+};
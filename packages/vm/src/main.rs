@@ -1,30 +1,39 @@
-#![feature(allocator_api)]
-#![feature(stdio_locked)]
+use std::{
+	io::{self, Write},
+	process,
+};
 
-use repr::alloc;
-
-#[macro_use]
-extern crate gramatika;
-extern crate lazy_static;
+use crossterm::tty::IsTty;
+use vm::{
+	cli::{self, Script},
+	repr::alloc,
+	Error, VM,
+};
 
 #[global_allocator]
 static ALLOCATOR: alloc::Spy = alloc::Spy;
 
-mod chunk;
-mod cli;
-mod compiler;
-mod debug;
 mod repl;
-mod repr;
-mod stack;
-mod vector;
-mod vm;
+
+// Exit codes from BSD's `sysexits.h`, as used by clox
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
 
 fn main() -> anyhow::Result<()> {
-	let args = cli::args()?;
+	let args = match cli::args() {
+		Ok(args) => args,
+		Err(err) => {
+			eprintln!("{}", err);
+			process::exit(EX_USAGE);
+		}
+	};
 
-	if let Some(example) = args.example {
-		vm::get().interpret(example).map(|_| ())?;
+	if let Some(script) = args.script {
+		run(script, args.script_args);
+	} else if args.plain || !io::stdin().is_tty() || !io::stdout().is_tty() {
+		repl::plain::start()?;
 	} else {
 		let term = cli::init()?;
 		let mem_spy = alloc::Spy::enable_logging();
@@ -37,3 +46,35 @@ fn main() -> anyhow::Result<()> {
 
 	Ok(())
 }
+
+/// Runs a script to completion and exits with a status reflecting how it went
+fn run(script: Script, args: Vec<String>) -> ! {
+	let src = match script.read() {
+		Ok(src) => src,
+		Err(err) => {
+			eprintln!("Couldn't read `{}`: {}", script, err);
+			process::exit(EX_IOERR);
+		}
+	};
+
+	let mut vm = VM::with_options(cli::vm_options());
+	vm.set_args(args);
+
+	let code = match vm.interpret(src) {
+		Ok(_) => 0,
+		Err(err) => match err.downcast_ref() {
+			Some(Error::Exit(code)) => *code,
+			Some(Error::Compile(_)) => {
+				eprintln!("{}", err);
+				EX_DATAERR
+			}
+			_ => {
+				eprintln!("{}", err);
+				EX_SOFTWARE
+			}
+		},
+	};
+
+	io::stdout().flush().ok();
+	process::exit(code);
+}
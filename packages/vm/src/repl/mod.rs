@@ -1,46 +1,73 @@
-use std::sync::mpsc::Receiver;
+use std::{
+	io::{self, BufRead, Read},
+	rc::Rc,
+	sync::mpsc::Receiver,
+};
 
 use nu_ansi_term::Color;
 
-use crate::{
-	cli::{self, Area, FmtColored},
-	vm,
+use vm::{
+	cli::{self, Area, AreaWriter},
+	Error, VM,
 };
 
+pub mod plain;
+
 pub fn start() -> anyhow::Result<()> {
-	for line in Repl::start() {
-		let result = vm::get().interpret(line.clone());
+	let repl = Repl::start();
+	let mut vm = VM::with_options(cli::vm_options());
+	vm.set_output(AreaWriter::new(Area::Output));
+
+	let stdin = repl.stdin.clone();
+	vm.set_input(InputReader::new(move || {
+		// A closed channel signals EOF
+		let line = match stdin.recv() {
+			Ok(line) => line,
+			Err(_) => return Ok(None),
+		};
+
 		let mut stdio = cli::stdio();
+		stdio
+			.writeln(Color::DarkGray.paint(&line), Area::Output)
+			.and_then(|_| stdio.flush())
+			.map_err(io::Error::other)?;
 
-		stdio.endl(Area::Output)?;
-		stdio.write(
-			format!(
-				"{} {} ",
-				Color::DarkGray.paint(line),
-				Color::DarkGray.paint("=>")
-			),
-			Area::Output,
-		)?;
-
-		match result {
-			Ok(Some(value)) => {
-				stdio.write(value.fmt_colored(), Area::Output)?;
-			}
-			Ok(None) => {
-				stdio.write(Color::DarkGray.italic().paint("void"), Area::Output)?;
+		Ok(Some(line))
+	}));
+
+	for input in repl {
+		let mut stdio = cli::stdio();
+		for line in input.lines() {
+			stdio.writeln(Color::DarkGray.paint(line), Area::Output)?;
+		}
+		stdio.flush()?;
+		drop(stdio);
+
+		let result = vm.interpret(input);
+		let mut stdio = cli::stdio();
+
+		if let Err(err) = result {
+			if let Some(Error::Exit(code)) = err.downcast_ref() {
+				stdio.exit(*code);
 			}
-			Err(err) => {
-				stdio.write(Color::Red.bold().paint("ERROR"), Area::Output)?;
-				stdio.writeln("", Area::Debug)?;
-
-				for line in err
-					.to_string()
-					.lines()
-					.rev()
-					.filter(|l| !l.is_empty())
-				{
-					stdio.writeln(Color::Red.paint(line), Area::Debug)?;
-				}
+
+			stdio.writeln(
+				format!(
+					"{} {}",
+					Color::DarkGray.paint("=>"),
+					Color::Red.bold().paint("ERROR")
+				),
+				Area::Output,
+			)?;
+			stdio.writeln("", Area::Debug)?;
+
+			for line in err
+				.to_string()
+				.lines()
+				.rev()
+				.filter(|l| !l.is_empty())
+			{
+				stdio.writeln(Color::Red.paint(line), Area::Debug)?;
 			}
 		}
 
@@ -51,13 +78,13 @@ pub fn start() -> anyhow::Result<()> {
 }
 
 struct Repl {
-	stdin: Receiver<String>,
+	stdin: Rc<Receiver<String>>,
 }
 
 impl Repl {
 	fn start() -> Self {
 		Self {
-			stdin: cli::stdio().stdin().unwrap(),
+			stdin: Rc::new(cli::stdio().stdin().unwrap()),
 		}
 	}
 }
@@ -69,3 +96,56 @@ impl Iterator for Repl {
 		self.stdin.recv().ok()
 	}
 }
+
+/// Feeds lines to the `input` native one at a time, so that it only takes the lines
+/// it asks for and leaves the rest to the REPL.
+struct InputReader<F> {
+	/// Returns the next line without its line ending, or `None` at EOF
+	next_line: F,
+	line: Vec<u8>,
+	pos: usize,
+}
+
+impl<F> InputReader<F>
+where F: FnMut() -> io::Result<Option<String>>
+{
+	fn new(next_line: F) -> Self {
+		Self {
+			next_line,
+			line: vec![],
+			pos: 0,
+		}
+	}
+}
+
+impl<F> Read for InputReader<F>
+where F: FnMut() -> io::Result<Option<String>>
+{
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.fill_buf()?.read(buf)?;
+		self.consume(read);
+
+		Ok(read)
+	}
+}
+
+impl<F> BufRead for InputReader<F>
+where F: FnMut() -> io::Result<Option<String>>
+{
+	fn fill_buf(&mut self) -> io::Result<&[u8]> {
+		if self.pos == self.line.len() {
+			// Leaving the buffer empty signals EOF
+			if let Some(line) = (self.next_line)()? {
+				self.line = line.into_bytes();
+				self.line.push(b'\n');
+				self.pos = 0;
+			}
+		}
+
+		Ok(&self.line[self.pos..])
+	}
+
+	fn consume(&mut self, amt: usize) {
+		self.pos = (self.pos + amt).min(self.line.len());
+	}
+}
@@ -0,0 +1,58 @@
+use std::io::{self, Write};
+
+use crossterm::tty::IsTty;
+use vm::{cli, Error, VM};
+
+use super::InputReader;
+
+/// A line-oriented REPL without the terminal UI, for when stdin or stdout isn't a
+/// terminal. Output from `print` goes to stdout and errors to stderr, as plain text.
+pub fn start() -> anyhow::Result<()> {
+	// Only prompt when there's someone there to see it
+	let prompt = io::stdin().is_tty();
+
+	let mut vm = VM::with_options(cli::vm_options());
+	vm.set_input(InputReader::new(read_line));
+
+	loop {
+		if prompt {
+			print!("> ");
+			io::stdout().flush()?;
+		}
+
+		let line = match read_line()? {
+			Some(line) => line,
+			None => break,
+		};
+
+		if let Err(err) = vm.interpret(line) {
+			if let Some(Error::Exit(code)) = err.downcast_ref() {
+				io::stdout().flush()?;
+				std::process::exit(*code);
+			}
+
+			eprintln!("{}", err);
+		}
+
+		io::stdout().flush()?;
+	}
+
+	Ok(())
+}
+
+/// Reads a line straight from stdin, without the line ending. Both the REPL and the
+/// `input` native read this way, so neither buffers lines meant for the other.
+fn read_line() -> io::Result<Option<String>> {
+	let mut line = String::new();
+	if io::stdin().read_line(&mut line)? == 0 {
+		return Ok(None);
+	}
+	if line.ends_with('\n') {
+		line.pop();
+		if line.ends_with('\r') {
+			line.pop();
+		}
+	}
+
+	Ok(Some(line))
+}
@@ -1,12 +1,16 @@
 use std::{
 	alloc::{self, Allocator, GlobalAlloc, Layout, System},
 	ptr::NonNull,
+};
+#[cfg(feature = "cli")]
+use std::{
 	thread::{self, JoinHandle},
 	time::Duration,
 };
 
 use parking_lot::Mutex;
 
+#[cfg(feature = "cli")]
 use crate::cli;
 
 lazy_static! {
@@ -21,6 +25,7 @@ pub struct MemState {
 	pub allocs: usize,
 }
 
+#[cfg(feature = "cli")]
 impl Spy {
 	pub fn enable_logging() -> JoinHandle<anyhow::Result<()>> {
 		thread::spawn(|| loop {
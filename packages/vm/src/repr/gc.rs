@@ -0,0 +1,118 @@
+use crate::table::Table;
+
+use super::{
+	object::{Obj, ObjKind},
+	Value,
+};
+
+/// Anything that holds references to heap objects which need to survive a garbage
+/// collection.
+///
+/// Implemented by the roots (the VM and the compiler), as well as by each object
+/// type, which traces the objects it refers to once it has been marked.
+pub trait Trace {
+	fn trace(&self, tracer: &mut Tracer);
+}
+
+/// The marking phase of a collection: marked objects are pushed onto a "gray" stack
+/// until their own references have been traced.
+#[derive(Default)]
+pub struct Tracer {
+	gray: Vec<Obj>,
+}
+
+impl Tracer {
+	pub fn mark(&mut self, mut obj: Obj) {
+		if obj.is_marked() {
+			return;
+		}
+
+		obj.set_marked(true);
+		self.gray.push(obj);
+	}
+
+	/// Traces the references of every gray object until there are none left, at
+	/// which point every reachable object has been marked.
+	pub(super) fn trace_references(&mut self) {
+		while let Some(obj) = self.gray.pop() {
+			match obj.kind() {
+				ObjKind::String => obj.as_string().unwrap().trace(self),
+				ObjKind::Function => obj.as_function().unwrap().trace(self),
+				ObjKind::Closure => obj.as_closure().unwrap().trace(self),
+				ObjKind::Upvalue => obj.as_upvalue().unwrap().trace(self),
+				ObjKind::Class => obj.as_class().unwrap().trace(self),
+				ObjKind::Instance => obj.as_instance().unwrap().trace(self),
+				ObjKind::BoundMethod => obj.as_bound_method().unwrap().trace(self),
+				ObjKind::Native => obj.as_native().unwrap().trace(self),
+				ObjKind::Userdata => obj.as_userdata().unwrap().trace(self),
+			}
+		}
+	}
+}
+
+impl Trace for () {
+	fn trace(&self, _: &mut Tracer) {}
+}
+
+impl Trace for Obj {
+	fn trace(&self, tracer: &mut Tracer) {
+		tracer.mark(*self);
+	}
+}
+
+impl Trace for Value {
+	fn trace(&self, tracer: &mut Tracer) {
+		if let Value::Obj(obj) = self {
+			tracer.mark(*obj);
+		}
+	}
+}
+
+impl<T: Trace> Trace for Option<T> {
+	fn trace(&self, tracer: &mut Tracer) {
+		if let Some(inner) = self {
+			inner.trace(tracer);
+		}
+	}
+}
+
+impl<T: Trace> Trace for [T] {
+	fn trace(&self, tracer: &mut Tracer) {
+		for item in self {
+			item.trace(tracer);
+		}
+	}
+}
+
+impl<A, B> Trace for (&A, &B)
+where
+	A: Trace + ?Sized,
+	B: Trace + ?Sized,
+{
+	fn trace(&self, tracer: &mut Tracer) {
+		self.0.trace(tracer);
+		self.1.trace(tracer);
+	}
+}
+
+impl<A, B, C> Trace for (&A, &B, &C)
+where
+	A: Trace + ?Sized,
+	B: Trace + ?Sized,
+	C: Trace + ?Sized,
+{
+	fn trace(&self, tracer: &mut Tracer) {
+		self.0.trace(tracer);
+		self.1.trace(tracer);
+		self.2.trace(tracer);
+	}
+}
+
+impl Trace for Table {
+	fn trace(&self, tracer: &mut Tracer) {
+		for (key, value) in self.iter() {
+			tracer.mark(key);
+			value.trace(tracer);
+		}
+	}
+}
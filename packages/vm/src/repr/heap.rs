@@ -0,0 +1,184 @@
+use rustc_hash::FxHashSet;
+
+use crate::table::Table;
+
+use super::{
+	gc::{Trace, Tracer},
+	object::{self, Obj, ObjString, Object},
+	Value,
+};
+
+/// Owns every object allocated by the compiler and the VM, linked together through
+/// their headers so they can be swept by the garbage collector, or all freed when
+/// the heap is dropped.
+///
+/// Strings are interned: `strings` holds every live string object, so that any two
+/// strings with the same contents are the same object.
+pub struct Heap {
+	objects: Option<Obj>,
+	/// The same objects as `objects`, for checking handles that come back from the
+	/// host before they're dereferenced
+	live: FxHashSet<Obj>,
+	strings: Table,
+	/// The approximate number of bytes owned by live objects
+	bytes_allocated: usize,
+	/// The value of `bytes_allocated` that will trigger the next collection
+	next_gc: usize,
+	/// Collect garbage before every allocation, to flush out rooting bugs
+	stress: bool,
+}
+
+impl Heap {
+	/// How much the heap is allowed to allocate before the first collection
+	const INITIAL_THRESHOLD: usize = 1024 * 1024;
+	/// After each collection, the next one is triggered when the number of bytes
+	/// allocated reaches this multiple of the number still in use
+	const GROW_FACTOR: usize = 2;
+
+	/// With `stress_gc`, the heap collects garbage before every allocation.
+	pub fn new(stress_gc: bool) -> Self {
+		Self {
+			objects: None,
+			live: FxHashSet::default(),
+			strings: Table::new(),
+			bytes_allocated: 0,
+			next_gc: Self::INITIAL_THRESHOLD,
+			stress: stress_gc,
+		}
+	}
+
+	/// Returns the existing string object for `chars` if there is one, or allocates
+	/// a new one otherwise.
+	pub fn intern<S>(&mut self, chars: S) -> Obj
+	where S: AsRef<str> + Into<Box<str>> {
+		if let Some(interned) = self.find(chars.as_ref()) {
+			return interned;
+		}
+
+		let hash = object::hash_str(chars.as_ref());
+		let obj = self.track(Obj::from_box(ObjString::new(chars.into(), hash)));
+		self.strings.set(obj, Value::Nil);
+
+		obj
+	}
+
+	/// Returns the existing string object for `chars`, if there is one.
+	pub fn find(&self, chars: &str) -> Option<Obj> {
+		self.strings
+			.find_string(chars, object::hash_str(chars))
+	}
+
+	/// Moves the object onto the heap. This never triggers a collection by itself;
+	/// callers are expected to call `collect_if_needed` beforehand, with the object
+	/// as one of the roots.
+	pub fn alloc<T: Object>(&mut self, object: Box<T>) -> Obj {
+		self.track(Obj::from_box(object))
+	}
+
+	/// Whether `obj` points to a live object, and so is safe to dereference. (A handle
+	/// to a collected object can't be told apart from one to a newer object that was
+	/// allocated at the same address, though.)
+	pub fn contains(&self, obj: Obj) -> bool {
+		self.live.contains(&obj)
+	}
+
+	/// Like `contains`, but borrows the handle from the heap, so that anything it's
+	/// dereferenced to can't outlive the borrow.
+	pub fn get(&self, obj: Obj) -> Option<&Obj> {
+		self.live.get(&obj)
+	}
+
+	/// Mutates an object that's already on the heap, counting any memory it gains
+	/// in the process (e.g. when an instance's field table grows) towards the next
+	/// collection.
+	pub fn modify<R>(&mut self, mut obj: Obj, f: impl FnOnce(&mut Obj) -> R) -> R {
+		let size = obj.size();
+		let result = f(&mut obj);
+		self.bytes_allocated = self.bytes_allocated - size + obj.size();
+
+		result
+	}
+
+	/// Collects garbage if enough memory has been allocated since the last
+	/// collection, or unconditionally in stress mode. `roots` must trace every
+	/// object that's still in use.
+	pub fn collect_if_needed(&mut self, roots: &dyn Trace) {
+		if self.stress || self.bytes_allocated > self.next_gc {
+			self.collect(roots);
+		}
+	}
+
+	pub fn collect(&mut self, roots: &dyn Trace) {
+		let mut tracer = Tracer::default();
+		roots.trace(&mut tracer);
+		tracer.trace_references();
+
+		// The string table doesn't keep its strings alive by itself
+		self.strings.remove_unmarked();
+		self.sweep();
+
+		self.next_gc =
+			(self.bytes_allocated * Self::GROW_FACTOR).max(Self::INITIAL_THRESHOLD);
+	}
+
+	/// Frees every object that wasn't marked, and clears the mark on the rest for
+	/// the next collection.
+	fn sweep(&mut self) {
+		let mut prev: Option<Obj> = None;
+		let mut current = self.objects;
+
+		while let Some(mut obj) = current {
+			current = obj.next();
+
+			if obj.is_marked() {
+				obj.set_marked(false);
+				prev = Some(obj);
+			} else {
+				match prev.as_mut() {
+					Some(prev) => prev.set_next(current),
+					None => self.objects = current,
+				}
+				self.bytes_allocated -= obj.size();
+				self.live.remove(&obj);
+				unsafe { obj.free() };
+			}
+		}
+	}
+
+	/// The number of objects currently allocated
+	#[cfg(test)]
+	pub fn len(&self) -> usize {
+		let mut count = 0;
+		let mut current = self.objects;
+		while let Some(obj) = current {
+			count += 1;
+			current = obj.next();
+		}
+
+		count
+	}
+
+	#[cfg(test)]
+	pub fn bytes_allocated(&self) -> usize {
+		self.bytes_allocated
+	}
+
+	fn track(&mut self, mut obj: Obj) -> Obj {
+		self.bytes_allocated += obj.size();
+		self.live.insert(obj);
+		obj.set_next(self.objects);
+		self.objects = Some(obj);
+
+		obj
+	}
+}
+
+impl Drop for Heap {
+	fn drop(&mut self) {
+		let mut current = self.objects.take();
+		while let Some(obj) = current {
+			current = obj.next();
+			unsafe { obj.free() };
+		}
+	}
+}
@@ -1,6 +1,15 @@
+pub use gc::{Trace, Tracer};
+pub(crate) use heap::Heap;
+pub use object::{
+	Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjKind,
+	ObjNative, ObjUpvalue, ObjUserdata, Object, UpvalueState, Userdata,
+};
 pub use value::Value;
 
 pub mod alloc;
+mod gc;
+mod heap;
+mod object;
 mod value;
 
 #[cfg(test)]
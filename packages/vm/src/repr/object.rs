@@ -0,0 +1,723 @@
+use std::{any::Any, fmt, mem, ptr::NonNull};
+
+use crate::{chunk::Chunk, table::Table, vm::NativeFn};
+
+use super::{
+	gc::{Trace, Tracer},
+	Value,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjKind {
+	String,
+	Function,
+	Closure,
+	Upvalue,
+	Class,
+	Instance,
+	BoundMethod,
+	Native,
+	Userdata,
+}
+
+/// Common header for every heap-allocated object. Each concrete object type is
+/// `#[repr(C)]` with the header as its first field, so a pointer to the header can
+/// be safely cast to a pointer to the full object once we've checked its `kind`.
+#[repr(C)]
+pub struct ObjHeader {
+	kind: ObjKind,
+	/// Set during the mark phase of a garbage collection for each reachable object
+	is_marked: bool,
+	next: Option<Obj>,
+}
+
+/// A copyable, untyped handle to a heap-allocated object. Since strings are
+/// interned, two handles are equal if and only if they point to the same object.
+///
+/// Nothing stops a handle from outliving its object, so only the VM can dereference
+/// them; hosts go through `VM::str` and `VM::userdata`, which check that the object
+/// is still alive.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Obj(NonNull<ObjHeader>);
+
+/// Marker for the concrete object types that can be allocated on the `Heap`. Objects
+/// trace the other objects they refer to, so that those are kept alive as long as
+/// the object itself is.
+///
+/// # Safety
+/// Implementors must be `#[repr(C)]` with an `ObjHeader` as their first field, and
+/// that header's `kind` must correctly identify the implementing type.
+pub unsafe trait Object: Trace {}
+
+#[repr(C)]
+pub struct ObjString {
+	header: ObjHeader,
+	hash: u32,
+	chars: Box<str>,
+}
+
+#[repr(C)]
+pub struct ObjFunction {
+	header: ObjHeader,
+	arity: u8,
+	upvalue_count: u8,
+	chunk: Chunk,
+	/// `None` for the top-level script
+	name: Option<Obj>,
+}
+
+/// The runtime representation of a function, pairing an `ObjFunction` with the
+/// variables it captured from its enclosing scopes.
+#[repr(C)]
+pub struct ObjClosure {
+	header: ObjHeader,
+	function: Obj,
+	upvalues: Box<[Obj]>,
+}
+
+/// A variable captured by a closure.
+#[repr(C)]
+pub struct ObjUpvalue {
+	header: ObjHeader,
+	state: UpvalueState,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum UpvalueState {
+	/// The variable still lives on the stack, at the given slot
+	Open(usize),
+	/// The variable has gone out of scope, so the upvalue holds onto it directly
+	Closed(Value),
+}
+
+#[repr(C)]
+pub struct ObjClass {
+	header: ObjHeader,
+	name: Obj,
+	/// Closures keyed by method name
+	methods: Table,
+}
+
+#[repr(C)]
+pub struct ObjInstance {
+	header: ObjHeader,
+	class: Obj,
+	fields: Table,
+}
+
+/// A method accessed as a property of an instance, which remembers the instance it
+/// was accessed from so that `this` can be bound when it's called. The method is
+/// either a closure or, for host classes, a native.
+#[repr(C)]
+pub struct ObjBoundMethod {
+	header: ObjHeader,
+	receiver: Value,
+	method: Obj,
+}
+
+/// A function implemented in Rust.
+#[repr(C)]
+pub struct ObjNative {
+	header: ObjHeader,
+	name: Obj,
+	arity: u8,
+	function: NativeFn,
+}
+
+/// An opaque Rust value owned by the VM, which scripts can call the methods of its
+/// class on.
+#[repr(C)]
+pub struct ObjUserdata {
+	header: ObjHeader,
+	class: Obj,
+	data: Box<dyn Userdata>,
+}
+
+/// A Rust type that can be handed to scripts as userdata (see `VM::host_userdata`).
+pub trait Userdata: AsAny + 'static {
+	/// Marks any Lox values the userdata holds onto, so that they're kept alive for as
+	/// long as it is.
+	fn trace(&self, _: &mut Tracer) {}
+}
+
+/// Lets `dyn Userdata` be downcast to its concrete type. Implemented for every type.
+pub trait AsAny {
+	fn as_any(&self) -> &dyn Any;
+	fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+
+	fn as_any_mut(&mut self) -> &mut dyn Any {
+		self
+	}
+}
+
+unsafe impl Object for ObjString {}
+unsafe impl Object for ObjFunction {}
+unsafe impl Object for ObjClosure {}
+unsafe impl Object for ObjUpvalue {}
+unsafe impl Object for ObjClass {}
+unsafe impl Object for ObjInstance {}
+unsafe impl Object for ObjBoundMethod {}
+unsafe impl Object for ObjNative {}
+unsafe impl Object for ObjUserdata {}
+
+impl ObjHeader {
+	fn new(kind: ObjKind) -> Self {
+		Self {
+			kind,
+			is_marked: false,
+			next: None,
+		}
+	}
+}
+
+impl Obj {
+	pub(crate) fn kind(&self) -> ObjKind {
+		self.header().kind
+	}
+
+	pub(crate) fn as_string(&self) -> Option<&ObjString> {
+		if self.kind() == ObjKind::String {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_function(&self) -> Option<&ObjFunction> {
+		if self.kind() == ObjKind::Function {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_closure(&self) -> Option<&ObjClosure> {
+		if self.kind() == ObjKind::Closure {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_upvalue(&self) -> Option<&ObjUpvalue> {
+		if self.kind() == ObjKind::Upvalue {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_upvalue_mut(&mut self) -> Option<&mut ObjUpvalue> {
+		if self.kind() == ObjKind::Upvalue {
+			Some(unsafe { self.cast_mut() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_class(&self) -> Option<&ObjClass> {
+		if self.kind() == ObjKind::Class {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_class_mut(&mut self) -> Option<&mut ObjClass> {
+		if self.kind() == ObjKind::Class {
+			Some(unsafe { self.cast_mut() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_instance(&self) -> Option<&ObjInstance> {
+		if self.kind() == ObjKind::Instance {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_instance_mut(&mut self) -> Option<&mut ObjInstance> {
+		if self.kind() == ObjKind::Instance {
+			Some(unsafe { self.cast_mut() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_bound_method(&self) -> Option<&ObjBoundMethod> {
+		if self.kind() == ObjKind::BoundMethod {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_native(&self) -> Option<&ObjNative> {
+		if self.kind() == ObjKind::Native {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_userdata(&self) -> Option<&ObjUserdata> {
+		if self.kind() == ObjKind::Userdata {
+			Some(unsafe { self.cast() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn as_userdata_mut(&mut self) -> Option<&mut ObjUserdata> {
+		if self.kind() == ObjKind::Userdata {
+			Some(unsafe { self.cast_mut() })
+		} else {
+			None
+		}
+	}
+
+	pub(crate) fn is_marked(&self) -> bool {
+		self.header().is_marked
+	}
+
+	pub(super) fn set_marked(&mut self, is_marked: bool) {
+		unsafe { self.0.as_mut().is_marked = is_marked }
+	}
+
+	/// Roughly how many bytes the object owns, as counted towards the next garbage
+	/// collection. Classes and instances own tables that grow after allocation, so
+	/// they need to be mutated through `Heap::modify` to keep the heap's count in
+	/// step with this.
+	pub(super) fn size(&self) -> usize {
+		match self.kind() {
+			ObjKind::String => {
+				mem::size_of::<ObjString>() + self.as_string().unwrap().chars.len()
+			}
+			ObjKind::Function => {
+				mem::size_of::<ObjFunction>() + self.as_function().unwrap().chunk.size()
+			}
+			ObjKind::Closure => {
+				let upvalues = self.as_closure().unwrap().upvalues.len();
+				mem::size_of::<ObjClosure>() + upvalues * mem::size_of::<Obj>()
+			}
+			ObjKind::Upvalue => mem::size_of::<ObjUpvalue>(),
+			ObjKind::Class => {
+				mem::size_of::<ObjClass>() + self.as_class().unwrap().methods.size()
+			}
+			ObjKind::Instance => {
+				mem::size_of::<ObjInstance>() + self.as_instance().unwrap().fields.size()
+			}
+			ObjKind::BoundMethod => mem::size_of::<ObjBoundMethod>(),
+			ObjKind::Native => mem::size_of::<ObjNative>(),
+			ObjKind::Userdata => {
+				let data = &self.as_userdata().unwrap().data;
+				mem::size_of::<ObjUserdata>() + mem::size_of_val(&**data)
+			}
+		}
+	}
+
+	pub(super) fn next(&self) -> Option<Obj> {
+		self.header().next
+	}
+
+	pub(super) fn set_next(&mut self, next: Option<Obj>) {
+		unsafe { self.0.as_mut().next = next }
+	}
+
+	/// Takes ownership of a boxed object, leaking it until it's passed to `free`.
+	pub(super) fn from_box<T: Object>(object: Box<T>) -> Self {
+		let ptr = Box::into_raw(object) as *mut ObjHeader;
+		Self(unsafe { NonNull::new_unchecked(ptr) })
+	}
+
+	/// # Safety
+	/// The object must have been created by `from_box` and must not be accessed
+	/// through this or any other handle after this call.
+	pub(super) unsafe fn free(self) {
+		match self.kind() {
+			ObjKind::String => drop(Box::from_raw(self.0.as_ptr() as *mut ObjString)),
+			ObjKind::Function => drop(Box::from_raw(self.0.as_ptr() as *mut ObjFunction)),
+			ObjKind::Closure => drop(Box::from_raw(self.0.as_ptr() as *mut ObjClosure)),
+			ObjKind::Upvalue => drop(Box::from_raw(self.0.as_ptr() as *mut ObjUpvalue)),
+			ObjKind::Class => drop(Box::from_raw(self.0.as_ptr() as *mut ObjClass)),
+			ObjKind::Instance => drop(Box::from_raw(self.0.as_ptr() as *mut ObjInstance)),
+			ObjKind::BoundMethod => {
+				drop(Box::from_raw(self.0.as_ptr() as *mut ObjBoundMethod))
+			}
+			ObjKind::Native => drop(Box::from_raw(self.0.as_ptr() as *mut ObjNative)),
+			ObjKind::Userdata => drop(Box::from_raw(self.0.as_ptr() as *mut ObjUserdata)),
+		}
+	}
+
+	fn header(&self) -> &ObjHeader {
+		unsafe { self.0.as_ref() }
+	}
+
+	unsafe fn cast<T>(&self) -> &T {
+		&*(self.0.as_ptr() as *const T)
+	}
+
+	unsafe fn cast_mut<T>(&mut self) -> &mut T {
+		&mut *(self.0.as_ptr() as *mut T)
+	}
+}
+
+impl fmt::Display for Obj {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.kind() {
+			ObjKind::String => self.as_string().unwrap().fmt(f),
+			ObjKind::Function => self.as_function().unwrap().fmt(f),
+			ObjKind::Closure => self.as_closure().unwrap().function().fmt(f),
+			ObjKind::Upvalue => write!(f, "upvalue"),
+			ObjKind::Class => self.as_class().unwrap().name.fmt(f),
+			ObjKind::Instance => {
+				write!(f, "{} instance", self.as_instance().unwrap().class().name)
+			}
+			ObjKind::BoundMethod => self.as_bound_method().unwrap().method.fmt(f),
+			ObjKind::Native => {
+				write!(f, "<native fn {}>", self.as_native().unwrap().name)
+			}
+			ObjKind::Userdata => {
+				write!(f, "{} instance", self.as_userdata().unwrap().class().name)
+			}
+		}
+	}
+}
+
+impl fmt::Debug for Obj {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.kind() {
+			ObjKind::String => f
+				.debug_tuple("String")
+				.field(&self.as_string().unwrap().as_str())
+				.finish(),
+			ObjKind::Function => write!(f, "Function({})", self),
+			ObjKind::Closure => write!(f, "Closure({})", self),
+			ObjKind::Upvalue => f
+				.debug_tuple("Upvalue")
+				.field(&self.as_upvalue().unwrap().state())
+				.finish(),
+			ObjKind::Class => write!(f, "Class({})", self),
+			ObjKind::Instance => write!(f, "Instance({})", self),
+			ObjKind::BoundMethod => write!(f, "BoundMethod({})", self),
+			ObjKind::Native => write!(f, "Native({})", self),
+			ObjKind::Userdata => write!(f, "Userdata({})", self),
+		}
+	}
+}
+
+impl ObjString {
+	pub(super) fn new(chars: Box<str>, hash: u32) -> Box<Self> {
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::String),
+			hash,
+			chars,
+		})
+	}
+
+	pub(crate) fn as_str(&self) -> &str {
+		&self.chars
+	}
+
+	pub fn hash(&self) -> u32 {
+		self.hash
+	}
+}
+
+impl fmt::Display for ObjString {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.as_str().fmt(f)
+	}
+}
+
+impl ObjFunction {
+	pub fn new(
+		arity: u8,
+		upvalue_count: u8,
+		chunk: Chunk,
+		name: Option<Obj>,
+	) -> Box<Self> {
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::Function),
+			arity,
+			upvalue_count,
+			chunk,
+			name,
+		})
+	}
+
+	pub fn arity(&self) -> u8 {
+		self.arity
+	}
+
+	pub fn upvalue_count(&self) -> u8 {
+		self.upvalue_count
+	}
+
+	pub fn chunk(&self) -> &Chunk {
+		&self.chunk
+	}
+
+	/// `None` for the top-level script
+	pub fn name(&self) -> Option<Obj> {
+		self.name
+	}
+}
+
+impl fmt::Display for ObjFunction {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.name {
+			Some(name) => write!(f, "<fn {}>", name),
+			None => write!(f, "<script>"),
+		}
+	}
+}
+
+impl ObjClosure {
+	pub fn new(function: Obj, upvalues: Box<[Obj]>) -> Box<Self> {
+		debug_assert!(function.as_function().is_some());
+
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::Closure),
+			function,
+			upvalues,
+		})
+	}
+
+	pub fn function(&self) -> &ObjFunction {
+		self.function.as_function().unwrap()
+	}
+
+	pub fn upvalues(&self) -> &[Obj] {
+		&self.upvalues
+	}
+}
+
+impl ObjUpvalue {
+	pub fn new(slot: usize) -> Box<Self> {
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::Upvalue),
+			state: UpvalueState::Open(slot),
+		})
+	}
+
+	pub fn state(&self) -> UpvalueState {
+		self.state
+	}
+
+	/// Moves the captured variable off of the stack and into the upvalue.
+	pub fn close(&mut self, value: Value) {
+		self.state = UpvalueState::Closed(value);
+	}
+
+	/// Updates a closed upvalue. Open upvalues are updated through the stack instead.
+	pub fn set(&mut self, value: Value) {
+		if let UpvalueState::Closed(closed) = &mut self.state {
+			*closed = value;
+		}
+	}
+}
+
+impl ObjClass {
+	pub fn new(name: Obj) -> Box<Self> {
+		debug_assert!(name.as_string().is_some());
+
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::Class),
+			name,
+			methods: Table::new(),
+		})
+	}
+
+	/// Returns the closure (or native) for the method named `name`, if the class has
+	/// one.
+	pub fn method(&self, name: Obj) -> Option<Obj> {
+		match self.methods.get(name)? {
+			Value::Obj(method) => Some(method),
+			_ => None,
+		}
+	}
+
+	pub fn set_method(&mut self, name: Obj, method: Obj) {
+		debug_assert!(method.as_closure().is_some() || method.as_native().is_some());
+		self.methods.set(name, Value::Obj(method));
+	}
+
+	/// Copies all of the superclass's methods into this class. This happens before
+	/// any of the subclass's own methods are defined, so they can override these.
+	pub fn inherit(&mut self, superclass: &ObjClass) {
+		superclass.methods.add_all(&mut self.methods);
+	}
+}
+
+impl ObjInstance {
+	pub fn new(class: Obj) -> Box<Self> {
+		debug_assert!(class.as_class().is_some());
+
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::Instance),
+			class,
+			fields: Table::new(),
+		})
+	}
+
+	pub fn class(&self) -> &ObjClass {
+		self.class.as_class().unwrap()
+	}
+
+	pub fn field(&self, name: Obj) -> Option<Value> {
+		self.fields.get(name)
+	}
+
+	pub fn set_field(&mut self, name: Obj, value: Value) {
+		self.fields.set(name, value);
+	}
+}
+
+impl ObjBoundMethod {
+	pub fn new(receiver: Value, method: Obj) -> Box<Self> {
+		debug_assert!(method.as_closure().is_some() || method.as_native().is_some());
+
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::BoundMethod),
+			receiver,
+			method,
+		})
+	}
+
+	pub fn receiver(&self) -> Value {
+		self.receiver
+	}
+
+	/// The method's closure or native
+	pub fn method(&self) -> Obj {
+		self.method
+	}
+}
+
+impl ObjNative {
+	pub fn new(name: Obj, arity: u8, function: NativeFn) -> Box<Self> {
+		debug_assert!(name.as_string().is_some());
+
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::Native),
+			name,
+			arity,
+			function,
+		})
+	}
+
+	pub fn arity(&self) -> u8 {
+		self.arity
+	}
+
+	pub fn function(&self) -> NativeFn {
+		self.function
+	}
+}
+
+impl ObjUserdata {
+	pub fn new(class: Obj, data: Box<dyn Userdata>) -> Box<Self> {
+		debug_assert!(class.as_class().is_some());
+
+		Box::new(Self {
+			header: ObjHeader::new(ObjKind::Userdata),
+			class,
+			data,
+		})
+	}
+
+	pub fn class(&self) -> &ObjClass {
+		self.class.as_class().unwrap()
+	}
+
+	pub fn data<T: Userdata>(&self) -> Option<&T> {
+		// Through the trait object, since `Box<dyn Userdata>` is `AsAny` itself
+		(*self.data).as_any().downcast_ref()
+	}
+
+	pub fn data_mut<T: Userdata>(&mut self) -> Option<&mut T> {
+		(*self.data).as_any_mut().downcast_mut()
+	}
+}
+
+impl Trace for ObjString {
+	fn trace(&self, _: &mut Tracer) {}
+}
+
+impl Trace for ObjFunction {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.name.trace(tracer);
+		self.chunk.trace(tracer);
+	}
+}
+
+impl Trace for ObjClosure {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.function.trace(tracer);
+		self.upvalues.trace(tracer);
+	}
+}
+
+impl Trace for ObjUpvalue {
+	fn trace(&self, tracer: &mut Tracer) {
+		// Open upvalues point into the stack, which is traced separately
+		if let UpvalueState::Closed(value) = self.state {
+			value.trace(tracer);
+		}
+	}
+}
+
+impl Trace for ObjClass {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.name.trace(tracer);
+		self.methods.trace(tracer);
+	}
+}
+
+impl Trace for ObjInstance {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.class.trace(tracer);
+		self.fields.trace(tracer);
+	}
+}
+
+impl Trace for ObjBoundMethod {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.receiver.trace(tracer);
+		self.method.trace(tracer);
+	}
+}
+
+impl Trace for ObjNative {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.name.trace(tracer);
+	}
+}
+
+impl Trace for ObjUserdata {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.class.trace(tracer);
+		Userdata::trace(&*self.data, tracer);
+	}
+}
+
+/// 32-bit FNV-1a
+pub(super) fn hash_str(chars: &str) -> u32 {
+	let mut hash = 2_166_136_261_u32;
+	for byte in chars.bytes() {
+		hash ^= byte as u32;
+		hash = hash.wrapping_mul(16_777_619);
+	}
+
+	hash
+}
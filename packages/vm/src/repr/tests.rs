@@ -0,0 +1,146 @@
+use crate::chunk::Chunk;
+
+use super::*;
+
+#[test]
+fn strings_are_interned() {
+	let mut heap = Heap::new(false);
+	let a = heap.intern("foo");
+	let b = heap.intern(String::from("foo"));
+	let c = heap.intern("bar");
+
+	assert_eq!(a, b);
+	assert_ne!(a, c);
+	assert_eq!(Value::Obj(a), Value::Obj(b));
+	assert_ne!(Value::Obj(a), Value::Nil);
+}
+
+#[test]
+fn strings_are_truthy() {
+	let mut heap = Heap::new(false);
+	let empty = Value::Obj(heap.intern(""));
+
+	assert!(!empty.is_falsy());
+	assert_eq!(empty.as_str(), Some(""));
+}
+
+#[test]
+fn strings_display_their_contents() {
+	let mut heap = Heap::new(false);
+	let value = Value::Obj(heap.intern("Hello, world!"));
+
+	assert_eq!(format!("{}", value), "Hello, world!");
+	assert_eq!(format!("{:?}", value), r#"Obj(String("Hello, world!"))"#);
+}
+
+#[test]
+fn functions_display_their_names() {
+	let mut heap = Heap::new(false);
+	let name = heap.intern("foo");
+	let function = heap.alloc(ObjFunction::new(0, 0, Chunk::new(), Some(name)));
+	let script = heap.alloc(ObjFunction::new(0, 0, Chunk::new(), None));
+
+	assert_eq!(function.kind(), ObjKind::Function);
+	assert_eq!(format!("{}", Value::Obj(function)), "<fn foo>");
+	assert_eq!(format!("{}", Value::Obj(script)), "<script>");
+}
+
+#[test]
+fn closures_display_as_their_function() {
+	let mut heap = Heap::new(false);
+	let name = heap.intern("foo");
+	let function = heap.alloc(ObjFunction::new(0, 0, Chunk::new(), Some(name)));
+	let closure = heap.alloc(ObjClosure::new(function, Box::new([])));
+
+	assert_eq!(closure.kind(), ObjKind::Closure);
+	assert_eq!(format!("{}", Value::Obj(closure)), "<fn foo>");
+}
+
+#[test]
+fn upvalues_can_be_closed() {
+	let mut heap = Heap::new(false);
+	let mut upvalue = heap.alloc(ObjUpvalue::new(3));
+
+	let as_upvalue = upvalue.as_upvalue_mut().unwrap();
+	assert!(matches!(as_upvalue.state(), UpvalueState::Open(3)));
+
+	as_upvalue.close(Value::Number(42.));
+	as_upvalue.set(Value::Number(69.));
+	assert!(matches!(
+		upvalue.as_upvalue().unwrap().state(),
+		UpvalueState::Closed(Value::Number(n)) if n == 69.
+	));
+}
+
+#[test]
+fn classes_and_instances() {
+	let mut heap = Heap::new(false);
+	let name = heap.intern("Breakfast");
+	let mut class = heap.alloc(ObjClass::new(name));
+	let instance = heap.alloc(ObjInstance::new(class));
+
+	assert_eq!(format!("{}", Value::Obj(class)), "Breakfast");
+	assert_eq!(format!("{}", Value::Obj(instance)), "Breakfast instance");
+
+	let cook = heap.intern("cook");
+	let function = heap.alloc(ObjFunction::new(0, 0, Chunk::new(), Some(cook)));
+	let closure = heap.alloc(ObjClosure::new(function, Box::new([])));
+	class
+		.as_class_mut()
+		.unwrap()
+		.set_method(cook, closure);
+
+	let method = instance
+		.as_instance()
+		.unwrap()
+		.class()
+		.method(cook);
+	assert_eq!(method, Some(closure));
+
+	let bound = heap.alloc(ObjBoundMethod::new(Value::Obj(instance), closure));
+	assert_eq!(format!("{}", Value::Obj(bound)), "<fn cook>");
+}
+
+#[test]
+fn unreachable_objects_are_collected() {
+	let mut heap = Heap::new(false);
+	let kept = heap.intern("kept");
+	let name = heap.intern("function");
+	let function = heap.alloc(ObjFunction::new(0, 0, Chunk::new(), Some(name)));
+	heap.intern("garbage");
+	assert_eq!(heap.len(), 4);
+
+	// The function keeps its name alive
+	heap.collect(&(&kept, &function));
+	assert_eq!(heap.len(), 3);
+	assert!(!kept.is_marked());
+
+	heap.collect(&());
+	assert_eq!(heap.len(), 0);
+}
+
+#[test]
+fn objects_growing_in_place_count_towards_collection() {
+	let mut heap = Heap::new(false);
+	let name = heap.intern("Breakfast");
+	let class = heap.alloc(ObjClass::new(name));
+	let instance = heap.alloc(ObjInstance::new(class));
+	let fields = (0..100)
+		.map(|i| heap.intern(format!("field{}", i)))
+		.collect::<Vec<_>>();
+
+	let before = heap.bytes_allocated();
+	for field in fields {
+		heap.modify(instance, |instance| {
+			instance
+				.as_instance_mut()
+				.unwrap()
+				.set_field(field, Value::Nil)
+		});
+	}
+	assert!(heap.bytes_allocated() >= before + 100 * std::mem::size_of::<Value>());
+
+	// Freeing the instance gives back everything it grew by
+	heap.collect(&());
+	assert_eq!(heap.bytes_allocated(), 0);
+}
@@ -1,10 +1,13 @@
 use std::{fmt, mem, str::FromStr};
 
-#[derive(Clone, Copy, Debug, PartialOrd)]
+use super::Obj;
+
+#[derive(Clone, Copy, Debug)]
 pub enum Value {
 	Number(f64),
 	Bool(bool),
 	Nil,
+	Obj(Obj),
 }
 
 impl Value {
@@ -13,6 +16,14 @@ impl Value {
 			Value::Number(_) => false,
 			Value::Bool(b) => !b,
 			Value::Nil => true,
+			Value::Obj(_) => false,
+		}
+	}
+
+	pub(crate) fn as_str(&self) -> Option<&str> {
+		match self {
+			Value::Obj(obj) => obj.as_string().map(|s| s.as_str()),
+			_ => None,
 		}
 	}
 }
@@ -41,6 +52,12 @@ impl From<bool> for Value {
 	}
 }
 
+impl From<Obj> for Value {
+	fn from(obj: Obj) -> Self {
+		Value::Obj(obj)
+	}
+}
+
 impl<T> From<Option<T>> for Value
 where T: Into<Value>
 {
@@ -57,6 +74,7 @@ impl PartialEq for Value {
 		match (self, other) {
 			(Self::Number(lhs), Self::Number(rhs)) => (lhs - rhs).abs() < f64::EPSILON,
 			(Self::Bool(lhs), Self::Bool(rhs)) => lhs == rhs,
+			(Self::Obj(lhs), Self::Obj(rhs)) => lhs == rhs,
 			_ => mem::discriminant(self) == mem::discriminant(other),
 		}
 	}
@@ -68,6 +86,7 @@ impl fmt::Display for Value {
 			Value::Number(n) => n.fmt(f),
 			Value::Bool(b) => b.fmt(f),
 			Value::Nil => write!(f, "nil"),
+			Value::Obj(obj) => obj.fmt(f),
 		}
 	}
 }
@@ -1,8 +1,14 @@
 use std::{
 	alloc::{self, Layout},
-	fmt, mem, ptr,
+	mem,
+	ops::{Index, IndexMut},
+	ptr, slice,
 };
 
+#[cfg(feature = "cli")]
+use std::fmt;
+
+#[cfg(feature = "cli")]
 use crate::cli::FmtColored;
 
 #[cfg(test)]
@@ -15,7 +21,7 @@ pub struct Stack<T> {
 }
 
 impl<T> Stack<T> {
-	pub const MAX: usize = 256;
+	pub const MAX: usize = 64 * 256;
 
 	pub fn new() -> Self {
 		assert!(mem::size_of::<T>() != 0);
@@ -57,6 +63,21 @@ impl<T> Stack<T> {
 		}
 	}
 
+	/// Returns a reference to the value `distance` slots down from the top of the stack.
+	pub fn peek(&self, distance: usize) -> Option<&T> {
+		if distance >= self.size {
+			None
+		} else {
+			Some(unsafe { &*self.end.sub(distance + 1) })
+		}
+	}
+
+	/// Returns the topmost `count` values, from the bottom up.
+	pub fn top(&self, count: usize) -> &[T] {
+		assert!(count <= self.size, "Stack index out of bounds: {}", count);
+		unsafe { slice::from_raw_parts(self.end.sub(count), count) }
+	}
+
 	pub fn mutate<F>(&mut self, mut mutate: F)
 	where F: FnMut(&mut T) {
 		if self.is_empty() {
@@ -71,14 +92,43 @@ impl<T> Stack<T> {
 		while self.pop().is_some() {}
 	}
 
+	/// Pops values off the top of the stack until its size is `len`.
+	pub fn truncate(&mut self, len: usize) {
+		while self.size > len {
+			self.pop();
+		}
+	}
+
 	pub fn is_empty(&self) -> bool {
 		self.size == 0
 	}
 
-	#[allow(dead_code)]
 	pub fn size(&self) -> usize {
 		self.size
 	}
+
+	/// How many more values can be pushed before the stack is full
+	pub fn room(&self) -> usize {
+		Self::MAX - self.size
+	}
+}
+
+/// Indexes from the bottom of the stack, e.g. to access a local variable by its slot
+/// relative to the base of the current frame.
+impl<T> Index<usize> for Stack<T> {
+	type Output = T;
+
+	fn index(&self, idx: usize) -> &Self::Output {
+		assert!(idx < self.size, "Stack index out of bounds: {}", idx);
+		unsafe { &*self.begin.add(idx) }
+	}
+}
+
+impl<T> IndexMut<usize> for Stack<T> {
+	fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+		assert!(idx < self.size, "Stack index out of bounds: {}", idx);
+		unsafe { &mut *self.begin.add(idx) }
+	}
 }
 
 impl<T> Drop for Stack<T> {
@@ -90,6 +140,7 @@ impl<T> Drop for Stack<T> {
 	}
 }
 
+#[cfg(feature = "cli")]
 impl<T> fmt::Debug for Stack<T>
 where T: FmtColored
 {
@@ -35,3 +35,42 @@ fn it_works() {
 	}
 	assert!(stack.is_empty());
 }
+
+#[test]
+fn peek() {
+	let mut stack = Stack::new();
+	assert_eq!(stack.peek(0), None);
+
+	stack.push(1);
+	stack.push(2);
+	stack.push(3);
+
+	assert_eq!(stack.peek(0), Some(&3));
+	assert_eq!(stack.peek(2), Some(&1));
+	assert_eq!(stack.peek(3), None);
+	assert_eq!(stack.size(), 3);
+}
+
+#[test]
+fn index() {
+	let mut stack = Stack::new();
+	stack.push(1);
+	stack.push(2);
+	stack.push(3);
+
+	assert_eq!(stack[0], 1);
+	assert_eq!(stack[2], 3);
+
+	stack[1] = 42;
+	assert_eq!(stack.pop(), Some(3));
+	assert_eq!(stack.pop(), Some(42));
+}
+
+#[test]
+#[should_panic]
+fn index_out_of_bounds() {
+	let mut stack = Stack::new();
+	stack.push(1);
+
+	let _ = stack[1];
+}
@@ -0,0 +1,213 @@
+use std::mem;
+
+use crate::{
+	repr::{Obj, Value},
+	vector::{vector, Vector},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// An open-addressing hash table with linear probing, keyed by string objects.
+///
+/// Because strings are interned, keys are compared by identity rather than by
+/// contents. The one exception is `find_string`, which is how the interning itself
+/// is implemented.
+pub struct Table {
+	entries: Vector<Entry>,
+	/// The number of occupied entries, *including* tombstones
+	count: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+	key: Option<Obj>,
+	value: Value,
+}
+
+impl Entry {
+	const EMPTY: Self = Self {
+		key: None,
+		value: Value::Nil,
+	};
+
+	const TOMBSTONE: Self = Self {
+		key: None,
+		value: Value::Bool(true),
+	};
+
+	fn is_tombstone(&self) -> bool {
+		self.key.is_none() && !matches!(self.value, Value::Nil)
+	}
+}
+
+impl Table {
+	const MAX_LOAD: f64 = 0.75;
+
+	pub fn new() -> Self {
+		Self {
+			entries: vector![],
+			count: 0,
+		}
+	}
+
+	pub fn get(&self, key: Obj) -> Option<Value> {
+		if self.count == 0 {
+			return None;
+		}
+
+		let entry = &self.entries[find_entry(&self.entries, key)];
+		entry.key.map(|_| entry.value)
+	}
+
+	/// Returns `true` if `key` wasn't already present in the table.
+	pub fn set(&mut self, key: Obj, value: Value) -> bool {
+		if (self.count + 1) as f64 > self.capacity() as f64 * Self::MAX_LOAD {
+			self.grow();
+		}
+
+		let idx = find_entry(&self.entries, key);
+		let entry = &mut self.entries[idx];
+		let is_new = entry.key.is_none();
+
+		// Reusing a tombstone doesn't change the count, since it was already counted
+		if is_new && !entry.is_tombstone() {
+			self.count += 1;
+		}
+
+		entry.key = Some(key);
+		entry.value = value;
+
+		is_new
+	}
+
+	/// Returns `true` if `key` was present in the table.
+	pub fn delete(&mut self, key: Obj) -> bool {
+		if self.count == 0 {
+			return false;
+		}
+
+		let idx = find_entry(&self.entries, key);
+		let entry = &mut self.entries[idx];
+		if entry.key.is_none() {
+			return false;
+		}
+
+		// Leave a tombstone so that probe sequences passing through this entry aren't
+		// cut short
+		*entry = Entry::TOMBSTONE;
+
+		true
+	}
+
+	/// Copies every entry from this table into `other`.
+	pub fn add_all(&self, other: &mut Table) {
+		for (key, value) in self.iter() {
+			other.set(key, value);
+		}
+	}
+
+	/// Deletes every entry whose key wasn't marked during the current garbage
+	/// collection, so that the keys can be freed.
+	pub fn remove_unmarked(&mut self) {
+		for entry in self.entries.iter_mut() {
+			if matches!(entry.key, Some(key) if !key.is_marked()) {
+				*entry = Entry::TOMBSTONE;
+			}
+		}
+	}
+
+	/// Looks up a string key by its contents instead of by identity.
+	pub fn find_string(&self, chars: &str, hash: u32) -> Option<Obj> {
+		if self.count == 0 {
+			return None;
+		}
+
+		let mask = self.capacity() - 1;
+		let mut idx = hash as usize & mask;
+
+		loop {
+			let entry = &self.entries[idx];
+			match entry.key {
+				None if !entry.is_tombstone() => return None,
+				Some(key) => {
+					let string = key.as_string().unwrap();
+					if string.hash() == hash && string.as_str() == chars {
+						return Some(key);
+					}
+				}
+				None => {}
+			}
+
+			idx = (idx + 1) & mask;
+		}
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (Obj, Value)> + '_ {
+		self.entries
+			.iter()
+			.filter_map(|entry| entry.key.map(|key| (key, entry.value)))
+	}
+
+	/// The number of bytes allocated for the table's entries
+	pub fn size(&self) -> usize {
+		self.capacity() * mem::size_of::<Entry>()
+	}
+
+	fn capacity(&self) -> usize {
+		self.entries.len()
+	}
+
+	fn grow(&mut self) {
+		let capacity = if self.capacity() < 8 {
+			8
+		} else {
+			self.capacity() * 2
+		};
+
+		let mut entries = Vector::with_capacity(capacity);
+		for _ in 0..capacity {
+			entries.push(Entry::EMPTY);
+		}
+
+		// Tombstones are dropped on the floor, so we need to recount
+		self.count = 0;
+		for entry in self.entries.iter() {
+			if let Some(key) = entry.key {
+				let idx = find_entry(&entries, key);
+				entries[idx] = *entry;
+				self.count += 1;
+			}
+		}
+
+		self.entries = entries;
+	}
+}
+
+/// Returns the index of the entry for `key` if it's present, or the index where it
+/// should be inserted otherwise. `entries.len()` must be a non-zero power of two.
+fn find_entry(entries: &[Entry], key: Obj) -> usize {
+	let mask = entries.len() - 1;
+	let mut idx = hash_key(key) as usize & mask;
+	let mut tombstone = None;
+
+	loop {
+		let entry = &entries[idx];
+		match entry.key {
+			Some(k) if k == key => return idx,
+			None if entry.is_tombstone() => {
+				tombstone.get_or_insert(idx);
+			}
+			None => return tombstone.unwrap_or(idx),
+			_ => {}
+		}
+
+		idx = (idx + 1) & mask;
+	}
+}
+
+fn hash_key(key: Obj) -> u32 {
+	key.as_string()
+		.expect("Table keys must be strings")
+		.hash()
+}
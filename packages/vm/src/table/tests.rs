@@ -0,0 +1,96 @@
+use crate::repr::{Heap, Value};
+
+use super::*;
+
+#[test]
+fn it_works() {
+	let mut heap = Heap::new(false);
+	let mut table = Table::new();
+
+	let foo = heap.intern("foo");
+	let bar = heap.intern("bar");
+
+	assert!(table.set(foo, 1.0.into()));
+	assert!(table.set(bar, 2.0.into()));
+	assert!(!table.set(foo, 3.0.into()));
+
+	assert_eq!(table.get(foo), Some(Value::Number(3.)));
+	assert_eq!(table.get(bar), Some(Value::Number(2.)));
+	assert_eq!(table.get(heap.intern("baz")), None);
+}
+
+#[test]
+fn it_can_delete_entries() {
+	let mut heap = Heap::new(false);
+	let mut table = Table::new();
+
+	let keys = (0..32)
+		.map(|i| heap.intern(format!("key{}", i)))
+		.collect::<Vec<_>>();
+
+	for (i, key) in keys.iter().enumerate() {
+		table.set(*key, (i as f64).into());
+	}
+	for key in keys.iter().step_by(2) {
+		assert!(table.delete(*key));
+	}
+	assert!(!table.delete(keys[0]));
+
+	// Entries probed past a tombstone must still be reachable
+	for (i, key) in keys.iter().enumerate() {
+		if i % 2 == 0 {
+			assert_eq!(table.get(*key), None);
+		} else {
+			assert_eq!(table.get(*key), Some(Value::Number(i as f64)));
+		}
+	}
+
+	// Tombstones are reused for new entries
+	assert!(table.set(keys[0], Value::Nil));
+	assert_eq!(table.get(keys[0]), Some(Value::Nil));
+}
+
+#[test]
+fn it_grows_past_its_initial_capacity() {
+	let mut heap = Heap::new(false);
+	let mut table = Table::new();
+
+	for i in 0..1000 {
+		table.set(heap.intern(format!("{}", i)), (i as f64).into());
+	}
+
+	assert_eq!(table.iter().count(), 1000);
+	for i in 0..1000 {
+		let key = heap.intern(format!("{}", i));
+		assert_eq!(table.get(key), Some(Value::Number(i as f64)));
+	}
+}
+
+#[test]
+fn it_finds_strings_by_content() {
+	let mut heap = Heap::new(false);
+	let mut table = Table::new();
+
+	let key = heap.intern("needle");
+	table.set(key, Value::Nil);
+
+	let hash = key.as_string().unwrap().hash();
+	assert_eq!(table.find_string("needle", hash), Some(key));
+	assert_eq!(table.find_string("haystack", hash), None);
+}
+
+#[test]
+fn add_all_copies_every_entry() {
+	let mut heap = Heap::new(false);
+	let mut from = Table::new();
+	let mut to = Table::new();
+
+	from.set(heap.intern("a"), 1.0.into());
+	from.set(heap.intern("b"), 2.0.into());
+	to.set(heap.intern("b"), 3.0.into());
+
+	from.add_all(&mut to);
+
+	assert_eq!(to.get(heap.intern("a")), Some(Value::Number(1.)));
+	assert_eq!(to.get(heap.intern("b")), Some(Value::Number(2.)));
+}
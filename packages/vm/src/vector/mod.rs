@@ -47,6 +47,26 @@ impl<T> Vector<T> {
 		}
 	}
 
+	pub fn with_capacity(cap: usize) -> Self {
+		let mut vec = Self::new();
+		if cap > 0 {
+			let layout = Layout::array::<T>(cap).unwrap();
+			let ptr = unsafe { alloc::alloc(layout) };
+
+			vec.ptr = match NonNull::new(ptr as *mut T) {
+				Some(ptr) => ptr,
+				None => alloc::handle_alloc_error(layout),
+			};
+			vec.cap = cap;
+		}
+
+		vec
+	}
+
+	pub fn capacity(&self) -> usize {
+		self.cap
+	}
+
 	pub(super) fn ptr(&self) -> *mut T {
 		self.ptr.as_ptr()
 	}
@@ -0,0 +1,235 @@
+use std::{error, fmt};
+
+use crate::{
+	repr::{Obj, Value},
+	vm::{Error, VM},
+};
+
+/// Converts a Rust value into a Lox value, allocating on the VM's heap if needed.
+///
+/// Derivable for structs with named fields, which become instances of a class with
+/// the struct's name (see `VM::host_class`).
+pub trait IntoLox {
+	fn into_lox(self, vm: &mut VM) -> Value;
+}
+
+/// Converts a Lox value into a Rust value, failing if it isn't of the right type.
+///
+/// Derivable for structs with named fields, which are read from the fields of an
+/// instance with the same names.
+pub trait FromLox: Sized {
+	fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConversionError {
+	Type {
+		expected: &'static str,
+		found: String,
+	},
+	/// A number that can't be represented by the target integer type
+	OutOfRange {
+		expected: &'static str,
+		found: f64,
+	},
+	MissingField(&'static str),
+	Field {
+		name: &'static str,
+		source: Box<ConversionError>,
+	},
+}
+
+impl ConversionError {
+	pub fn expected(expected: &'static str, found: Value, vm: &VM) -> Self {
+		// A value the host held onto for too long can't even be printed
+		let found = if vm.is_live(found) {
+			found.to_string()
+		} else {
+			"a garbage-collected value".into()
+		};
+
+		ConversionError::Type { expected, found }
+	}
+
+	/// Adds the name of the field being converted to an error.
+	pub fn in_field(self, name: &'static str) -> Self {
+		ConversionError::Field {
+			name,
+			source: Box::new(self),
+		}
+	}
+}
+
+impl error::Error for ConversionError {}
+
+impl fmt::Display for ConversionError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConversionError::Type { expected, found } => {
+				write!(f, "Expected {}, found `{}`", expected, found)
+			}
+			ConversionError::OutOfRange { expected, found } => {
+				write!(f, "Expected {}, found `{}`", expected, found)
+			}
+			ConversionError::MissingField(name) => write!(f, "Missing field `{}`", name),
+			ConversionError::Field { name, source } => {
+				write!(f, "{} in field `{}`", source, name)
+			}
+		}
+	}
+}
+
+impl From<ConversionError> for Error {
+	fn from(err: ConversionError) -> Self {
+		Error::runtime(err.to_string())
+	}
+}
+
+impl IntoLox for Value {
+	fn into_lox(self, _: &mut VM) -> Value {
+		self
+	}
+}
+
+impl FromLox for Value {
+	fn from_lox(value: Value, _: &VM) -> Result<Self, ConversionError> {
+		Ok(value)
+	}
+}
+
+impl IntoLox for Obj {
+	fn into_lox(self, _: &mut VM) -> Value {
+		Value::Obj(self)
+	}
+}
+
+impl IntoLox for () {
+	fn into_lox(self, _: &mut VM) -> Value {
+		Value::Nil
+	}
+}
+
+impl FromLox for () {
+	fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError> {
+		match value {
+			Value::Nil => Ok(()),
+			other => Err(ConversionError::expected("nil", other, vm)),
+		}
+	}
+}
+
+impl IntoLox for bool {
+	fn into_lox(self, _: &mut VM) -> Value {
+		Value::Bool(self)
+	}
+}
+
+impl FromLox for bool {
+	fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError> {
+		match value {
+			Value::Bool(b) => Ok(b),
+			other => Err(ConversionError::expected("a bool", other, vm)),
+		}
+	}
+}
+
+impl IntoLox for f64 {
+	fn into_lox(self, _: &mut VM) -> Value {
+		Value::Number(self)
+	}
+}
+
+impl FromLox for f64 {
+	fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError> {
+		match value {
+			Value::Number(n) => Ok(n),
+			other => Err(ConversionError::expected("a number", other, vm)),
+		}
+	}
+}
+
+impl IntoLox for f32 {
+	fn into_lox(self, _: &mut VM) -> Value {
+		Value::Number(self as f64)
+	}
+}
+
+impl FromLox for f32 {
+	fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError> {
+		f64::from_lox(value, vm).map(|n| n as f32)
+	}
+}
+
+// Lox only has floating-point numbers, so integers are converted from numbers that
+// happen to be whole and in range
+macro_rules! integers {
+	($($int:ty),*) => {$(
+		impl IntoLox for $int {
+			fn into_lox(self, _: &mut VM) -> Value {
+				Value::Number(self as f64)
+			}
+		}
+
+		impl FromLox for $int {
+			fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError> {
+				let n = f64::from_lox(value, vm)?;
+
+				// `MAX as f64` rounds up for the 64-bit types, so the upper bound is
+				// checked against the power of two just past `MAX` instead
+				let signed = <$int>::MIN != 0;
+				let end = 2f64.powi(<$int>::BITS as i32 - signed as i32);
+
+				if n.fract() != 0. || n < <$int>::MIN as f64 || n >= end {
+					return Err(ConversionError::OutOfRange {
+						expected: concat!("a valid `", stringify!($int), "`"),
+						found: n,
+					});
+				}
+
+				Ok(n as $int)
+			}
+		}
+	)*};
+}
+
+integers!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoLox for String {
+	fn into_lox(self, vm: &mut VM) -> Value {
+		Value::Obj(vm.intern(self))
+	}
+}
+
+impl IntoLox for &str {
+	fn into_lox(self, vm: &mut VM) -> Value {
+		Value::Obj(vm.intern(self.into()))
+	}
+}
+
+impl FromLox for String {
+	fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError> {
+		match vm.str(value) {
+			Some(chars) => Ok(chars.into()),
+			None => Err(ConversionError::expected("a string", value, vm)),
+		}
+	}
+}
+
+/// `None` is `nil`
+impl<T: IntoLox> IntoLox for Option<T> {
+	fn into_lox(self, vm: &mut VM) -> Value {
+		match self {
+			Some(value) => value.into_lox(vm),
+			None => Value::Nil,
+		}
+	}
+}
+
+impl<T: FromLox> FromLox for Option<T> {
+	fn from_lox(value: Value, vm: &VM) -> Result<Self, ConversionError> {
+		match value {
+			Value::Nil => Ok(None),
+			other => T::from_lox(other, vm).map(Some),
+		}
+	}
+}
@@ -1,26 +1,30 @@
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, feature = "cli"))]
 use std::{
 	cell::UnsafeCell,
 	fmt::{Alignment, Write},
 };
 
+#[cfg(all(debug_assertions, feature = "cli"))]
 use nu_ansi_term::Color;
+#[cfg(all(debug_assertions, feature = "cli"))]
 use strip_ansi_escapes as ansi;
 
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, feature = "cli"))]
 use Alignment::*;
 
 use crate::{
-	chunk::{Lines, OpCode},
-	cli::{self, Area, DebugFlags, FmtColored},
+	chunk::{OpCode, SourceMap},
 	repr::Value,
 	stack::Stack,
 };
 
-#[cfg(debug_assertions)]
-use crate::debug::Repeat;
+#[cfg(all(debug_assertions, feature = "cli"))]
+use crate::{
+	cli::{self, DebugFlags, FmtColored},
+	debug::Repeat,
+};
 
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, feature = "cli"))]
 pub(super) struct Disassembler {
 	buf: UnsafeCell<String>,
 	col: UnsafeCell<isize>,
@@ -28,7 +32,7 @@ pub(super) struct Disassembler {
 
 // TODO - Replace messy crate::debug module with this
 
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, feature = "cli"))]
 impl Disassembler {
 	// Column offsets for the output
 	const ADDR: isize = 0;
@@ -45,15 +49,13 @@ impl Disassembler {
 
 	pub fn write_header(&self, _: &str) {
 		if cli::debug_flags().contains(DebugFlags::EXEC) {
-			let mut stdio = cli::stdio();
-			stdio.writeln("", Area::Debug).unwrap();
-			stdio.flush().unwrap();
+			cli::write_debug("").unwrap();
 		}
 	}
 
-	pub fn write_preamble(&self, offset: usize, lines: &Lines) {
+	pub fn write_preamble(&self, offset: usize, source_map: &SourceMap) {
 		self.write_offset(offset);
-		self.write_line(offset, lines);
+		self.write_line(offset, source_map);
 	}
 
 	fn write_offset(&self, offset: usize) {
@@ -66,12 +68,12 @@ impl Disassembler {
 		self.write(data, Left);
 	}
 
-	fn write_line(&self, offset: usize, lines: &Lines) {
+	fn write_line(&self, offset: usize, source_map: &SourceMap) {
 		self.set_col(Self::LINE);
 
-		let line = lines.find_line(offset);
+		let line = source_map.find_line(offset);
 		let prev_line = if offset > 0 {
-			Some(lines.find_line(offset - 1))
+			Some(source_map.find_line(offset - 1))
 		} else {
 			None
 		};
@@ -94,7 +96,17 @@ impl Disassembler {
 		let name = format!("{:?}", op);
 		let name = match op {
 			Constant | Constant16 | Constant24 => Color::Green.paint(name),
+			DefineGlobal | DefineGlobal16 | DefineGlobal24 | GetGlobal | GetGlobal16
+			| GetGlobal24 | SetGlobal | SetGlobal16 | SetGlobal24 | GetLocal
+			| SetLocal | GetProperty | GetProperty16 | GetProperty24 | SetProperty
+			| SetProperty16 | SetProperty24 | GetSuper | GetSuper16 | GetSuper24 => {
+				Color::Yellow.paint(name)
+			}
 			True | False | Nil => Color::Cyan.paint(name),
+			Jump | JumpIfFalse | Loop | Call | Invoke | Invoke16 | Invoke24
+			| SuperInvoke | SuperInvoke16 | SuperInvoke24 => Color::Magenta.paint(name),
+			Closure | Closure16 | Closure24 | Class | Class16 | Class24 | Method
+			| Method16 | Method24 | Inherit => Color::Blue.paint(name),
 			_ => Color::Fixed(5).bold().paint(name),
 		};
 
@@ -106,7 +118,27 @@ impl Disassembler {
 		self.write(data, Left);
 	}
 
+	pub fn write_operand(&self, operand: usize) {
+		let data = format!(" {}", Color::DarkGray.paint(operand.to_string()));
+		self.write(data, Left);
+	}
+
+	pub fn write_jump_target(&self, target: usize) {
+		let data = format!(
+			" {} {}",
+			Color::DarkGray.paint("->"),
+			Color::DarkGray.paint(format!("{:#06x}", target)),
+		);
+		self.write(data, Left);
+	}
+
 	pub fn write_stack(&self, stack: &Stack<Value>) {
+		// Formatting a deep stack for every instruction is slow enough to be worth
+		// skipping when nobody's going to see it
+		if !cli::debug_flags().contains(DebugFlags::EXEC) {
+			return;
+		}
+
 		self.set_col(Self::STACK);
 
 		let data = format!("{:?}", stack);
@@ -116,9 +148,7 @@ impl Disassembler {
 	pub fn flush(&self) {
 		let buf = self.buf();
 		if cli::debug_flags().contains(DebugFlags::EXEC) {
-			let mut stdio = cli::stdio();
-			stdio.writeln(buf.clone(), Area::Debug).unwrap();
-			stdio.flush().unwrap();
+			cli::write_debug(buf).unwrap();
 		}
 		buf.clear();
 	}
@@ -165,17 +195,19 @@ impl Disassembler {
 	}
 }
 
-#[cfg(not(debug_assertions))]
+#[cfg(not(all(debug_assertions, feature = "cli")))]
 pub(super) struct Disassembler;
 
-#[cfg(not(debug_assertions))]
+#[cfg(not(all(debug_assertions, feature = "cli")))]
 #[rustfmt::skip]
 impl Disassembler {
 	#[inline(always)] pub fn new() -> Self { Self }
 	#[inline(always)] pub fn write_header(&self, _: &str) {}
-	#[inline(always)] pub fn write_preamble(&self, _: usize, _: &Lines) {}
+	#[inline(always)] pub fn write_preamble(&self, _: usize, _: &SourceMap) {}
 	#[inline(always)] pub fn write_opcode(&self, _: OpCode) {}
-	#[inline(always)] pub fn write_value(&self, _: Value) {}
+	#[inline(always)] pub fn write_value(&self, _: &Value) {}
+	#[inline(always)] pub fn write_operand(&self, _: usize) {}
+	#[inline(always)] pub fn write_jump_target(&self, _: usize) {}
 	#[inline(always)] pub fn write_stack(&self, _: &Stack<Value>) {}
 	#[inline(always)] pub fn flush(&self) {}
 }
@@ -1,9 +1,140 @@
 use std::fmt;
 
+use gramatika::{Span, SpannedError};
+
 #[derive(Debug)]
 pub enum Error {
-	Compile,
-	Runtime(String),
+	/// Every problem found while compiling a script, in source order
+	Compile(Vec<Diagnostic>),
+	Runtime(RuntimeError),
+	/// The script called `exit()`, which unwinds the VM and leaves it up to the host to
+	/// actually exit
+	Exit(i32),
+}
+
+/// An error raised while executing a script, along with the calls that were in
+/// progress when it happened.
+#[derive(Debug)]
+pub struct RuntimeError {
+	pub message: String,
+	/// Innermost call first. Empty until the error has unwound out of the VM's run
+	/// loop.
+	pub trace: Vec<TraceFrame>,
+	/// The code that was executing in the innermost frame, if it came from a script
+	pub snippet: Option<Snippet>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TraceFrame {
+	/// `None` for the top-level script
+	pub function: Option<String>,
+	/// The line of the instruction that was executing in this frame
+	pub line: usize,
+}
+
+/// A span of source code, along with the line it starts on for context
+#[derive(Debug, PartialEq)]
+pub struct Snippet {
+	pub text: String,
+	pub span: Span,
+}
+
+/// A problem found by the compiler, pointing at the code responsible for it.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub code: Code,
+	pub message: String,
+	/// `None` if the problem wasn't tied to any particular token, e.g. when the
+	/// source ended unexpectedly
+	pub snippet: Option<Snippet>,
+}
+
+/// How serious a `Diagnostic` is. Every diagnostic the compiler reports today is an
+/// error, which stops the script from running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+	Error,
+}
+
+/// The kind of problem a `Diagnostic` reports, which stays the same even if its
+/// message is reworded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+	/// The code doesn't match Lox's grammar
+	Syntax,
+	/// The left-hand side of an `=` can't be assigned to
+	InvalidAssignment,
+	/// A local variable was declared twice in the same scope
+	Redeclaration,
+	/// A variable or class refers to itself in its own declaration
+	SelfReference,
+	/// `this`, `super` or `return` used somewhere it has no meaning
+	InvalidContext,
+	/// A function, jump or argument list exceeds what the bytecode can encode
+	Limit,
+}
+
+impl Error {
+	/// A runtime error with no stack trace yet, e.g. to be returned from a native
+	pub fn runtime<S: Into<String>>(message: S) -> Self {
+		Error::Runtime(RuntimeError {
+			message: message.into(),
+			trace: vec![],
+			snippet: None,
+		})
+	}
+}
+
+impl Snippet {
+	/// The snippet for `span`, or `None` if `span` isn't in `source`
+	pub fn new(source: &str, span: Span) -> Option<Self> {
+		let text = source.lines().nth(span.start.line)?;
+
+		Some(Self {
+			text: text.into(),
+			span,
+		})
+	}
+}
+
+impl Diagnostic {
+	pub fn error<S: Into<String>>(
+		code: Code,
+		message: S,
+		source: &str,
+		span: Option<Span>,
+	) -> Self {
+		Self {
+			severity: Severity::Error,
+			code,
+			message: message.into(),
+			snippet: span.and_then(|span| Snippet::new(source, span)),
+		}
+	}
+
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+}
+
+impl From<SpannedError<'_>> for Diagnostic {
+	fn from(err: SpannedError) -> Self {
+		Diagnostic::error(Code::Syntax, err.message, err.source, err.span)
+	}
+}
+
+impl Code {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Code::Syntax => "E001",
+			Code::InvalidAssignment => "E002",
+			Code::Redeclaration => "E003",
+			Code::SelfReference => "E004",
+			Code::InvalidContext => "E005",
+			Code::Limit => "E006",
+		}
+	}
 }
 
 impl std::error::Error for Error {}
@@ -11,8 +142,97 @@ impl std::error::Error for Error {}
 impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
-			Error::Compile => write!(f, "CompileError"),
-			Error::Runtime(msg) => write!(f, "RuntimeError: {}", msg),
+			Error::Compile(diagnostics) => {
+				for (idx, diagnostic) in diagnostics.iter().enumerate() {
+					if idx > 0 {
+						writeln!(f)?;
+					}
+					write!(f, "{}", diagnostic)?;
+				}
+				Ok(())
+			}
+			Error::Runtime(err) => err.fmt(f),
+			Error::Exit(code) => write!(f, "Exited with code {}", code),
+		}
+	}
+}
+
+impl fmt::Display for RuntimeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "RuntimeError: {}", self.message)?;
+		if let Some(snippet) = &self.snippet {
+			write!(f, "\n{}", snippet)?;
+		}
+
+		// Collapse runs of the same frame, e.g. from a stack overflow
+		let mut frames = self.trace.iter().peekable();
+		while let Some(frame) = frames.next() {
+			write!(f, "\n{}", frame)?;
+
+			let mut repeats = 0;
+			while frames.next_if_eq(&frame).is_some() {
+				repeats += 1;
+			}
+			if repeats > 0 {
+				write!(f, "\n... repeated {} more times", repeats)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let severity = match self.severity {
+			Severity::Error => "CompileError",
+		};
+		write!(f, "{}[{}]: {}", severity, self.code.as_str(), self.message)?;
+		if let Some(snippet) = &self.snippet {
+			write!(f, "\n{}", snippet)?;
 		}
+
+		Ok(())
+	}
+}
+
+impl fmt::Display for TraceFrame {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match &self.function {
+			Some(name) => write!(f, "[line {}] in {}()", self.line, name),
+			None => write!(f, "[line {}] in script", self.line),
+		}
+	}
+}
+
+impl fmt::Display for Snippet {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let line = (self.span.start.line + 1).to_string();
+		let gutter = " ".repeat(line.len());
+
+		// Spans that continue onto later lines are underlined to the end of the first
+		let start = self.span.start.character;
+		let end = if self.span.end.line == self.span.start.line {
+			self.span.end.character
+		} else {
+			self.text.chars().count()
+		};
+		// Keep any tabs so the underline lines up with the text
+		let indent = self
+			.text
+			.chars()
+			.take(start)
+			.map(|c| if c == '\t' { '\t' } else { ' ' })
+			.collect::<String>();
+
+		writeln!(f, "{} |", gutter)?;
+		writeln!(f, "{} | {}", line, self.text)?;
+		write!(
+			f,
+			"{} | {}{}",
+			gutter,
+			indent,
+			"^".repeat(end.saturating_sub(start).max(1))
+		)
 	}
 }
@@ -0,0 +1,85 @@
+use crate::{
+	chunk::{Chunk, SourceMap},
+	repr::{Obj, ObjClosure, ObjFunction, Value},
+};
+
+/// An in-progress function call. Iterating over the frame reads its function's
+/// bytecode starting from the frame's instruction pointer, which can be moved
+/// backward or forward with `seek`.
+#[derive(Clone, Copy)]
+pub struct CallFrame {
+	closure: Obj,
+	ip: usize,
+	/// The index of the function's first stack slot, which holds the function itself
+	base: usize,
+}
+
+impl CallFrame {
+	pub fn new(closure: Obj, base: usize) -> Self {
+		debug_assert!(closure.as_closure().is_some());
+
+		Self {
+			closure,
+			ip: 0,
+			base,
+		}
+	}
+
+	pub fn closure_obj(&self) -> Obj {
+		self.closure
+	}
+
+	pub fn closure(&self) -> &ObjClosure {
+		self.closure.as_closure().unwrap()
+	}
+
+	pub fn function(&self) -> &ObjFunction {
+		self.closure().function()
+	}
+
+	pub fn chunk(&self) -> &Chunk {
+		self.function().chunk()
+	}
+
+	pub fn base(&self) -> usize {
+		self.base
+	}
+
+	/// The offset of the next byte to be read.
+	pub fn offset(&self) -> usize {
+		self.ip
+	}
+
+	pub fn seek(&mut self, offset: usize) {
+		assert!(
+			offset <= self.chunk().len(),
+			"Jumped out of bounds: {:#06x}",
+			offset
+		);
+		self.ip = offset;
+	}
+
+	pub fn read_const(&self, handle: usize) -> Option<Value> {
+		self.chunk().read_const(handle)
+	}
+
+	pub fn source_map(&self) -> &SourceMap {
+		self.chunk().source_map()
+	}
+
+	pub fn source(&self) -> &str {
+		self.chunk().source()
+	}
+}
+
+impl Iterator for CallFrame {
+	type Item = (usize, u8);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let byte = *self.chunk().get(self.ip)?;
+		let offset = self.ip;
+		self.ip += 1;
+
+		Some((offset, byte))
+	}
+}
@@ -1,56 +1,131 @@
-use std::{cell::UnsafeCell, convert::TryFrom};
+use std::{
+	convert::TryFrom,
+	io::{self, BufRead, BufReader, Write},
+};
 
 use crate::{
-	chunk::{self, JoinBytes, OpCode},
+	chunk::{JoinBytes, OpCode},
 	compiler,
-	repr::Value,
+	repr::{
+		Heap, Obj, ObjBoundMethod, ObjClass, ObjClosure, ObjInstance, ObjNative,
+		ObjUpvalue, ObjUserdata, Object, Trace, Tracer, UpvalueState, Userdata, Value,
+	},
 	stack::Stack,
+	table::Table,
+	vector::{vector, Vector},
 };
 
-use self::{debug::Disassembler, error::Error};
+use self::debug::Disassembler;
+
+pub use self::{
+	convert::{ConversionError, FromLox, IntoLox},
+	error::{Code, Diagnostic, Error, RuntimeError, Severity, Snippet, TraceFrame},
+	frame::CallFrame,
+	natives::{Native, NativeFn},
+};
 
+mod convert;
 mod debug;
 mod error;
+mod frame;
+mod natives;
 
-// TODO: https://github.com/munificent/craftinginterpreters/blob/6c2ea6f7192910053a78832f0cc34ad56b17ce7c/book/a-virtual-machine.md?plain=1#L50
-lazy_static! {
-	static ref INSTANCE: VM = VM::new();
+#[cfg(test)]
+mod tests;
+
+/// A Lox interpreter. Each VM owns its own heap and global scope, so any number of
+/// them can run independently of each other.
+pub struct VM {
+	frames: Vector<CallFrame>,
+	stack: Stack<Value>,
+	heap: Heap,
+	globals: Table,
+	/// Upvalues that still point into the stack, sorted by stack slot
+	open_upvalues: Vec<Obj>,
+	/// The interned name of class initializers, cached for calls to classes
+	init_string: Obj,
+	/// Values handed to the host, which are kept alive until it next calls into the
+	/// VM
+	host_values: Vec<Value>,
+	/// Classes for host data, keyed by name
+	host_classes: Table,
+	out: Box<dyn Write>,
+	input: Box<dyn BufRead>,
+	/// Command-line arguments for the script, read by the `argc` and `argv` natives
+	args: Vec<String>,
+	disasm: Disassembler,
 }
 
-pub fn get() -> &'static VM {
-	&INSTANCE
+/// Settings for a new VM, which can't be changed once it's created
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+	/// Collect garbage before every allocation, to flush out rooting bugs
+	pub stress_gc: bool,
 }
 
-pub struct VM {
-	ip: UnsafeCell<Option<chunk::Consumable>>,
-	stack: UnsafeCell<Stack<Value>>,
-	disasm: Disassembler,
+/// The VM's garbage collection roots, borrowed separately from its heap so that the
+/// heap can be mutated while they're traced.
+struct Roots<'a> {
+	frames: &'a Vector<CallFrame>,
+	stack: &'a Stack<Value>,
+	globals: &'a Table,
+	open_upvalues: &'a [Obj],
+	init_string: Obj,
+	host_values: &'a [Value],
+	host_classes: &'a Table,
+}
+
+impl Trace for Roots<'_> {
+	fn trace(&self, tracer: &mut Tracer) {
+		for slot in 0..self.stack.size() {
+			self.stack[slot].trace(tracer);
+		}
+		for frame in self.frames.iter() {
+			frame.closure_obj().trace(tracer);
+		}
+		self.globals.trace(tracer);
+		self.open_upvalues.trace(tracer);
+		self.init_string.trace(tracer);
+		self.host_values.trace(tracer);
+		self.host_classes.trace(tracer);
+	}
 }
 
-// FIXME: This is definitely not sound
-unsafe impl Send for VM {}
-unsafe impl Sync for VM {}
+macro_rules! roots {
+	($self:ident) => {
+		Roots {
+			frames: &$self.frames,
+			stack: &$self.stack,
+			globals: &$self.globals,
+			open_upvalues: &$self.open_upvalues,
+			init_string: $self.init_string,
+			host_values: &$self.host_values,
+			host_classes: &$self.host_classes,
+		}
+	};
+}
 
 macro_rules! binop {
-	($self:ident, $stack:ident, $op:tt) => {{
-		let rhs_v = $stack.pop().unwrap();
+	($self:ident, $op:tt) => {{
+		let rhs_v = $self.stack.pop().unwrap();
 		let rhs = match rhs_v {
 			Value::Number(n) => Ok(n),
-			other => Err(Error::Runtime(format!(
+			other => Err(Error::runtime(format!(
 				"Binary operator `{}` not applicable to value `{}`",
 				stringify!($op),
 				other,
 			))),
 		}?;
 
+		let disasm = &$self.disasm;
 		let mut result = Ok(());
-		$stack.mutate(|lhs_v| {
-			$self.disasm.write_value(lhs_v);
-			$self.disasm.write_value(&rhs_v);
+		$self.stack.mutate(|lhs_v| {
+			disasm.write_value(lhs_v);
+			disasm.write_value(&rhs_v);
 
 			match lhs_v {
 				Value::Number(lhs) => *lhs_v = (*lhs $op rhs).into(),
-				other => result = Err(Error::Runtime(format!(
+				other => result = Err(Error::runtime(format!(
 					"Binary operator `{}` not applicable to value `{}`",
 					stringify!($op),
 					other,
@@ -62,92 +137,738 @@ macro_rules! binop {
 	}}
 }
 
+impl Default for VM {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl VM {
-	fn new() -> Self {
-		VM {
-			ip: UnsafeCell::new(None),
-			stack: UnsafeCell::new(Stack::new()),
+	/// Maximum depth of nested function calls
+	const FRAMES_MAX: usize = 64;
+
+	pub fn new() -> Self {
+		Self::with_options(Options::default())
+	}
+
+	pub fn with_options(options: Options) -> Self {
+		let mut heap = Heap::new(options.stress_gc);
+		let init_string = heap.intern("init");
+
+		let mut vm = VM {
+			frames: vector![],
+			stack: Stack::new(),
+			heap,
+			globals: Table::new(),
+			open_upvalues: Vec::new(),
+			init_string,
+			host_values: Vec::new(),
+			host_classes: Table::new(),
+			out: Box::new(io::stdout()),
+			input: Box::new(BufReader::new(io::stdin())),
+			args: Vec::new(),
 			disasm: Disassembler::new(),
+		};
+
+		for native in natives::STDLIB {
+			vm.register_native(native);
+		}
+
+		vm
+	}
+
+	/// Redirects the output of `print` statements, which goes to stdout by default.
+	pub fn set_output<W>(&mut self, out: W)
+	where W: Write + 'static {
+		self.out = Box::new(out);
+	}
+
+	/// Replaces the source of lines read by the `input` native, which is stdin by
+	/// default.
+	pub fn set_input<R>(&mut self, input: R)
+	where R: BufRead + 'static {
+		self.input = Box::new(input);
+	}
+
+	/// Sets the arguments that scripts can read with the `argc` and `argv` natives,
+	/// which are empty by default.
+	pub fn set_args(&mut self, args: Vec<String>) {
+		self.args = args;
+	}
+
+	/// Defines a global function implemented in Rust.
+	pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+		let name = self.intern_string(name.into());
+		let native = self.alloc(ObjNative::new(name, arity, function));
+		self.globals.set(name, Value::Obj(native));
+	}
+
+	/// Defines a native declared with the `#[native]` attribute.
+	pub fn register_native(&mut self, native: &Native) {
+		self.define_native(native.name, native.arity, native.function);
+	}
+
+	/// Returns the interned string object for `chars`, e.g. to be passed to `call`
+	/// or returned from a native. Like values returned by `call`, it's kept alive
+	/// until the host next calls `call` or `interpret`, and after that only if it's
+	/// reachable from the script.
+	pub fn intern(&mut self, chars: String) -> Obj {
+		let obj = self.intern_string(chars);
+		self.host_values.push(Value::Obj(obj));
+
+		obj
+	}
+
+	/// Looks up a global variable, e.g. a function declared by a script that's
+	/// already been interpreted.
+	pub fn global(&self, name: &str) -> Option<Value> {
+		self.heap
+			.find(name)
+			.and_then(|name| self.globals.get(name))
+	}
+
+	/// Returns the class used for host data named `name`, creating it the first time
+	/// it's needed. These classes aren't visible to scripts as globals.
+	pub fn host_class(&mut self, name: &str) -> Obj {
+		let name = self.intern(name.into());
+		if let Some(Value::Obj(class)) = self.host_classes.get(name) {
+			return class;
 		}
+
+		let class = self.alloc(ObjClass::new(name));
+		self.host_classes.set(name, Value::Obj(class));
+
+		class
+	}
+
+	/// Creates an instance of the host class named `class` (see `host_class`), with
+	/// no fields.
+	pub fn host_instance(&mut self, class: &str) -> Obj {
+		let class = self.host_class(class);
+		let instance = self.alloc(ObjInstance::new(class));
+		self.host_values.push(Value::Obj(instance));
+
+		instance
 	}
 
-	pub fn interpret(&self, src: String) -> anyhow::Result<Option<Value>> {
-		let chunk = compiler::compile(src)?;
-		unsafe {
-			let ip = &mut *self.ip.get();
-			*ip = Some(chunk.into_iter());
+	/// Defines Rust methods on the host class named `class` (see `host_class`), e.g.
+	/// for userdata. Each method is called with the instance or userdata it was
+	/// accessed from (i.e., `this`) before its `arity` arguments.
+	pub fn define_class(&mut self, class: &str, methods: &[Native]) -> Obj {
+		let class = self.host_class(class);
+		for method in methods {
+			let name = self.intern_string(method.name.into());
+			let native = self.alloc(ObjNative::new(name, method.arity, method.function));
+			self.heap.modify(class, |class| {
+				class
+					.as_class_mut()
+					.unwrap()
+					.set_method(name, native)
+			});
 		}
 
-		self.disasm.write_header("chunk");
-		self.run()
+		class
 	}
 
-	fn run(&self) -> anyhow::Result<Option<Value>> {
-		use OpCode::*;
+	/// Hands `data` to the VM as an instance of the host class named `class`, whose
+	/// methods are defined with `define_class`. It can be borrowed back from the
+	/// returned value with `VM::userdata`.
+	pub fn host_userdata<T: Userdata>(&mut self, class: &str, data: T) -> Obj {
+		let class = self.host_class(class);
+		let userdata = self.alloc(ObjUserdata::new(class, Box::new(data)));
+		self.host_values.push(Value::Obj(userdata));
+
+		userdata
+	}
+
+	/// Reads a field of an instance, returning `None` if the value isn't an instance
+	/// or the field isn't set.
+	pub fn field(&self, instance: Value, name: &str) -> Option<Value> {
+		self.live_obj(instance)?
+			.as_instance()?
+			.field(self.heap.find(name)?)
+	}
+
+	/// Whether `value` is an instance of a class, e.g. one created by `host_instance`.
+	pub fn is_instance(&self, value: Value) -> bool {
+		self.live_obj(value)
+			.map(|obj| obj.as_instance().is_some())
+			.unwrap_or(false)
+	}
+
+	/// Borrows the contents of a string. Returns `None` if the value isn't a string,
+	/// or if it's been garbage collected since it was handed to the host.
+	pub fn str(&self, value: Value) -> Option<&str> {
+		self.live_obj(value)?
+			.as_string()
+			.map(|string| string.as_str())
+	}
+
+	/// Borrows the Rust value of a userdata object (see `host_userdata`), if it's a
+	/// `T` that hasn't been garbage collected.
+	pub fn userdata<T: Userdata>(&self, value: Value) -> Option<&T> {
+		self.live_obj(value)?.as_userdata()?.data()
+	}
 
-		let (ip, stack) = unsafe { (&mut *self.ip.get(), &mut *self.stack.get()) };
+	/// Mutably borrows the Rust value of a userdata object, like `userdata`. Since
+	/// values are copied freely, this goes through the VM so that the borrow is
+	/// exclusive.
+	pub fn userdata_mut<T: Userdata>(&mut self, value: Value) -> Option<&mut T> {
+		let mut obj = *self.live_obj(value)?;
+		let data: *mut T = obj.as_userdata_mut()?.data_mut()?;
+
+		// The object is alive, and nothing else can reach it (or collect it) for as
+		// long as the VM is mutably borrowed
+		Some(unsafe { &mut *data })
+	}
+
+	/// Sets a field of an instance.
+	///
+	/// # Panics
+	/// If `instance` isn't an instance, or either it or `value` has been garbage
+	/// collected.
+	pub fn set_field(&mut self, instance: Obj, name: &str, value: Value) {
 		assert!(
-			ip.is_some(),
-			"Called vm.run() with an unassigned instruction pointer"
+			self.heap.contains(instance) && self.is_live(value),
+			"Expected values that haven't been garbage collected"
 		);
 
-		let ip = ip.as_mut().unwrap();
-		while let Some((offset, byte)) = ip.next() {
-			self.disasm.write_preamble(offset, ip.lines());
+		let name = self.intern(name.into());
+		self.heap.modify(instance, |instance| {
+			instance
+				.as_instance_mut()
+				.expect("Expected an instance")
+				.set_field(name, value)
+		});
+	}
+
+	pub fn interpret(&mut self, src: String) -> anyhow::Result<()> {
+		self.host_values.clear();
+
+		let script = compiler::compile(src, &mut self.heap, &roots!(self))?;
+		let script = self.alloc(ObjClosure::new(script, Box::new([])));
+		self.reserve(1)?;
+		self.stack.push(Value::Obj(script));
+		self.frames.push(CallFrame::new(script, 0));
+
+		self.disasm.write_header("chunk");
+		self.run(0)
+			.map_err(|err| self.unwind(err, 0, 0))?;
+
+		Ok(())
+	}
+
+	/// Calls a function (or any other callable value) with the given arguments and
+	/// runs it to completion. This can be used by the host to run callbacks defined
+	/// by a script, or by natives to call back into Lox.
+	pub fn call(&mut self, callee: Value, args: &[Value]) -> Result<Value, Error> {
+		if !self.is_live(callee) || !args.iter().all(|arg| self.is_live(*arg)) {
+			return Err(Error::runtime(
+				"Can't call with a value that's been garbage collected.",
+			));
+		}
+
+		let depth = self.frames.len();
+		let base = self.stack.size();
+
+		self.reserve(args.len() + 1)?;
+		self.stack.push(callee);
+		for arg in args {
+			self.stack.push(*arg);
+		}
+		// Calls made by natives still need to keep the values they've created alive
+		if depth == 0 {
+			self.host_values.clear();
+		}
+
+		let result = self.call_value(args.len()).and_then(|_| {
+			if self.frames.len() > depth {
+				self.run(depth)
+			} else {
+				// Natives and classes without initializers don't push a frame, so their
+				// result is already on the stack
+				Ok(self.stack.pop().unwrap())
+			}
+		});
+
+		match result {
+			Ok(value) => {
+				self.host_values.push(value);
+				Ok(value)
+			}
+			Err(err) => match self.unwind(err, depth, base).downcast::<Error>() {
+				Ok(err) => Err(err),
+				Err(other) => Err(Error::runtime(other.to_string())),
+			},
+		}
+	}
+
+	/// Whether `value` is safe to dereference, which isn't a given for values the host
+	/// has held onto since before its last call into the VM.
+	pub(crate) fn is_live(&self, value: Value) -> bool {
+		match value {
+			Value::Obj(obj) => self.heap.contains(obj),
+			_ => true,
+		}
+	}
+
+	fn live_obj(&self, value: Value) -> Option<&Obj> {
+		match value {
+			Value::Obj(obj) => self.heap.get(obj),
+			_ => None,
+		}
+	}
+
+	/// Fails with a stack overflow unless `count` more values can be pushed onto the
+	/// stack. Besides the call depth limit, this catches deep recursion through
+	/// functions with many parameters or temporaries.
+	fn reserve(&self, count: usize) -> Result<(), Error> {
+		if self.stack.room() < count {
+			Err(Error::runtime("Stack overflow."))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Runs until the call frame at `depth` returns, returning its result.
+	fn run(&mut self, depth: usize) -> anyhow::Result<Value> {
+		// The current frame is cached here, and only written back to `frames` when
+		// another call is pushed on top of it, or when an error interrupts it
+		let mut frame = *self
+			.frames
+			.last()
+			.expect("Called vm.run() without a call frame");
+
+		let result = self.execute(&mut frame, depth);
+		if result.is_err() {
+			*self.frames.last_mut().unwrap() = frame;
+		}
+
+		result
+	}
+
+	/// Records the stack trace for a runtime error (unless it already has one, from a
+	/// nested call), then discards any frames and stack slots above the given depth
+	/// and stack size.
+	fn unwind(
+		&mut self,
+		mut err: anyhow::Error,
+		depth: usize,
+		base: usize,
+	) -> anyhow::Error {
+		if let Some(Error::Runtime(err)) = err.downcast_mut::<Error>() {
+			if err.trace.is_empty() {
+				err.trace = self.stack_trace();
+				err.snippet = self.snippet();
+			}
+		}
+
+		while self.frames.len() > depth {
+			self.frames.pop();
+		}
+		self.close_upvalues(base);
+		self.stack.truncate(base);
+
+		err
+	}
+
+	fn execute(&mut self, frame: &mut CallFrame, depth: usize) -> anyhow::Result<Value> {
+		use OpCode::*;
+
+		while let Some((offset, byte)) = frame.next() {
+			self.disasm
+				.write_preamble(offset, frame.source_map());
 
-			let op = OpCode::try_from(byte).map_err(|_| Error::Compile)?;
+			let op = OpCode::try_from(byte)
+				.map_err(|_| Error::runtime(format!("Invalid opcode: {}", byte)))?;
 			self.disasm.write_opcode(op);
 
+			// No instruction leaves more than one extra value on the stack
+			self.reserve(1)?;
+
 			#[rustfmt::skip]
 			#[allow(clippy::assign_op_pattern)]
 			match op {
 				Constant
 				| Constant16
-				| Constant24 => self.constant(op, ip, stack),
-				Nil          => stack.push(Value::Nil),
-				True         => stack.push(Value::Bool(true)),
-				False        => stack.push(Value::Bool(false)),
-				Add          => binop!(self, stack, +),
-				Subtract     => binop!(self, stack, -),
-				Multiply     => binop!(self, stack, *),
-				Divide       => binop!(self, stack, /),
-				Negate       => self.negate(stack)?,
-				Not          => self.not(stack),
-				Equal        => self.equal(stack),
-				Greater      => binop!(self, stack, >),
-				Less         => binop!(self, stack, <),
-				Return       => self.return_(stack),
+				| Constant24 => self.constant(op, frame),
+				Nil          => self.stack.push(Value::Nil),
+				True         => self.stack.push(Value::Bool(true)),
+				False        => self.stack.push(Value::Bool(false)),
+				Pop          => { self.stack.pop(); }
+				Add          => self.add()?,
+				Subtract     => binop!(self, -),
+				Multiply     => binop!(self, *),
+				Divide       => binop!(self, /),
+				Negate       => self.negate()?,
+				Not          => self.not(),
+				Equal        => self.equal(),
+				Greater      => binop!(self, >),
+				Less         => binop!(self, <),
+				Print        => self.print()?,
+				DefineGlobal
+				| DefineGlobal16
+				| DefineGlobal24 => self.define_global(op, frame),
+				GetGlobal
+				| GetGlobal16
+				| GetGlobal24 => self.get_global(op, frame)?,
+				SetGlobal
+				| SetGlobal16
+				| SetGlobal24 => self.set_global(op, frame)?,
+				GetLocal     => self.get_local(op, frame),
+				SetLocal     => self.set_local(op, frame),
+				GetUpvalue   => self.get_upvalue(op, frame),
+				SetUpvalue   => self.set_upvalue(op, frame),
+				CloseUpvalue => {
+					self.close_upvalues(self.stack.size() - 1);
+					self.stack.pop();
+				}
+				Jump
+				| Loop       => {
+					let target = self.read_jump(op, frame);
+					frame.seek(target);
+				}
+				JumpIfFalse  => {
+					let target = self.read_jump(op, frame);
+					if self.stack.peek(0).unwrap().is_falsy() {
+						frame.seek(target);
+					}
+				}
+				Call         => {
+					let argc = self.read_operand(op, frame);
+					*self.frames.last_mut().unwrap() = *frame;
+					self.call_value(argc)?;
+					*frame = *self.frames.last().unwrap();
+				}
+				Closure
+				| Closure16
+				| Closure24  => self.closure(op, frame),
+				Class
+				| Class16
+				| Class24    => self.class(op, frame),
+				GetProperty
+				| GetProperty16
+				| GetProperty24 => self.get_property(op, frame)?,
+				SetProperty
+				| SetProperty16
+				| SetProperty24 => self.set_property(op, frame)?,
+				Method
+				| Method16
+				| Method24   => self.method(op, frame),
+				Invoke
+				| Invoke16
+				| Invoke24   => {
+					let name = self.read_name(op, frame);
+					let argc = self.read_operand(op, frame);
+					*self.frames.last_mut().unwrap() = *frame;
+					self.invoke(name, argc)?;
+					*frame = *self.frames.last().unwrap();
+				}
+				Inherit      => self.inherit()?,
+				GetSuper
+				| GetSuper16
+				| GetSuper24 => self.get_super(op, frame)?,
+				SuperInvoke
+				| SuperInvoke16
+				| SuperInvoke24 => {
+					let name = self.read_name(op, frame);
+					let argc = self.read_operand(op, frame);
+					let superclass = self.pop_superclass();
+
+					*self.frames.last_mut().unwrap() = *frame;
+					let class = superclass.as_class().unwrap();
+					self.invoke_from_class(class, name, argc)?;
+					*frame = *self.frames.last().unwrap();
+				}
+				Return       => {
+					let result = self.stack.pop().unwrap();
+					self.disasm.write_value(&result);
+
+					self.frames.pop();
+					self.close_upvalues(frame.base());
+					self.stack.truncate(frame.base());
+
+					// Returning from the outermost call being run, e.g. the top-level
+					// script
+					if self.frames.len() == depth {
+						self.disasm.flush();
+						return Ok(result);
+					}
+
+					self.stack.push(result);
+					*frame = *self.frames.last().unwrap();
+				}
 			};
 
-			self.disasm.write_stack(stack);
+			self.disasm.write_stack(&self.stack);
 			self.disasm.flush();
 		}
 
-		Ok(stack.pop())
+		unreachable!("Reached the end of a chunk without returning");
 	}
 
-	fn constant(&self, op: OpCode, ip: &mut chunk::Consumable, stack: &mut Stack<Value>) {
-		let value = match op {
-			OpCode::Constant => ip.join_bytes(1),
-			OpCode::Constant16 => ip.join_bytes(2),
-			OpCode::Constant24 => ip.join_bytes(3),
-			_ => unreachable!(),
-		}
-		.and_then(|handle| ip.read_const(handle))
-		.expect("Error locating value in the pool");
+	/// Collects garbage if the heap is due for a collection, treating `extra` as a
+	/// root alongside the VM's own state.
+	fn collect_if_needed(&mut self, extra: &dyn Trace) {
+		self.heap
+			.collect_if_needed(&(&roots!(self), extra));
+	}
+
+	/// Allocates a new object on the heap, collecting garbage first if needed. The
+	/// object itself is treated as a root, so anything it refers to is kept alive
+	/// even if it's not reachable from the VM yet.
+	fn alloc<T: Object>(&mut self, object: Box<T>) -> Obj {
+		self.collect_if_needed(&*object);
+		self.heap.alloc(object)
+	}
+
+	fn intern_string(&mut self, chars: String) -> Obj {
+		self.collect_if_needed(&());
+		self.heap.intern(chars)
+	}
+
+	fn stack_trace(&self) -> Vec<TraceFrame> {
+		self.frames
+			.iter()
+			.rev()
+			.map(|frame| TraceFrame {
+				function: frame
+					.function()
+					.name()
+					.map(|name| name.to_string()),
+				// The instruction pointer is just past the instruction that was executing
+				line: frame
+					.source_map()
+					.find_line(frame.offset().saturating_sub(1)),
+			})
+			.collect()
+	}
+
+	/// The source code of the instruction executing in the innermost frame
+	fn snippet(&self) -> Option<Snippet> {
+		let frame = self.frames.last()?;
+		let span = frame
+			.source_map()
+			.find_span(frame.offset().saturating_sub(1));
+
+		Snippet::new(frame.source(), span)
+	}
+
+	fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+		self.input.read_line(buf)
+	}
+
+	fn read_const(&self, op: OpCode, frame: &mut CallFrame) -> Value {
+		let value = op
+			.const_width()
+			.and_then(|width| frame.join_bytes(width))
+			.and_then(|handle| frame.read_const(handle))
+			.expect("Error locating value in the pool");
 
 		self.disasm.write_value(&value);
-		stack.push(value);
+		value
+	}
+
+	fn read_name(&self, op: OpCode, frame: &mut CallFrame) -> Obj {
+		match self.read_const(op, frame) {
+			Value::Obj(name) if name.as_string().is_some() => name,
+			other => unreachable!("Expected a string constant, found `{}`", other),
+		}
+	}
+
+	fn read_operand(&self, op: OpCode, frame: &mut CallFrame) -> usize {
+		let operand = op
+			.operand_width()
+			.and_then(|width| frame.join_bytes(width))
+			.expect("Error reading instruction operand");
+
+		self.disasm.write_operand(operand);
+		operand
 	}
 
-	fn negate(&self, stack: &mut Stack<Value>) -> anyhow::Result<()> {
+	fn read_jump(&self, op: OpCode, frame: &mut CallFrame) -> usize {
+		let distance = op
+			.operand_width()
+			.and_then(|width| frame.join_bytes(width))
+			.expect("Error reading jump offset");
+		let target = op.jump_target(frame.offset(), distance).unwrap();
+
+		self.disasm.write_jump_target(target);
+		target
+	}
+
+	fn constant(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let value = self.read_const(op, frame);
+		self.stack.push(value);
+	}
+
+	fn define_global(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let name = self.read_name(op, frame);
+		let value = self.stack.pop().unwrap();
+
+		self.globals.set(name, value);
+	}
+
+	fn get_global(&mut self, op: OpCode, frame: &mut CallFrame) -> anyhow::Result<()> {
+		let name = self.read_name(op, frame);
+		match self.globals.get(name) {
+			Some(value) => {
+				self.stack.push(value);
+				Ok(())
+			}
+			None => Err(Error::runtime(format!("Undefined variable `{}`", name)).into()),
+		}
+	}
+
+	fn set_global(&mut self, op: OpCode, frame: &mut CallFrame) -> anyhow::Result<()> {
+		let name = self.read_name(op, frame);
+		let value = *self.stack.peek(0).unwrap();
+
+		// Assignment doesn't implicitly declare the variable
+		if self.globals.set(name, value) {
+			self.globals.delete(name);
+			return Err(Error::runtime(format!("Undefined variable `{}`", name)).into());
+		}
+
+		Ok(())
+	}
+
+	fn get_local(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let slot = self.read_operand(op, frame);
+		self.stack.push(self.stack[frame.base() + slot]);
+	}
+
+	fn set_local(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let slot = self.read_operand(op, frame);
+		self.stack[frame.base() + slot] = *self.stack.peek(0).unwrap();
+	}
+
+	fn get_upvalue(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let idx = self.read_operand(op, frame);
+		let upvalue = frame.closure().upvalues()[idx];
+
+		let value = match upvalue.as_upvalue().unwrap().state() {
+			UpvalueState::Open(slot) => self.stack[slot],
+			UpvalueState::Closed(value) => value,
+		};
+		self.stack.push(value);
+	}
+
+	fn set_upvalue(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let idx = self.read_operand(op, frame);
+		let mut upvalue = frame.closure().upvalues()[idx];
+		let value = *self.stack.peek(0).unwrap();
+
+		let upvalue = upvalue.as_upvalue_mut().unwrap();
+		match upvalue.state() {
+			UpvalueState::Open(slot) => self.stack[slot] = value,
+			UpvalueState::Closed(_) => upvalue.set(value),
+		}
+	}
+
+	fn closure(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let function = match self.read_const(op, frame) {
+			Value::Obj(function) if function.as_function().is_some() => function,
+			other => unreachable!("Expected a function constant, found `{}`", other),
+		};
+		let upvalue_count = function.as_function().unwrap().upvalue_count();
+
+		let upvalues = (0..upvalue_count)
+			.map(|_| {
+				let is_local = frame.join_bytes(1).unwrap() == 1;
+				let idx = frame.join_bytes(1).unwrap();
+
+				if is_local {
+					self.capture_upvalue(frame.base() + idx)
+				} else {
+					frame.closure().upvalues()[idx]
+				}
+			})
+			.collect();
+
+		let closure = self.alloc(ObjClosure::new(function, upvalues));
+		self.stack.push(Value::Obj(closure));
+	}
+
+	/// Returns the open upvalue for the given stack slot, creating it if needed, so
+	/// that closures capturing the same variable share a single upvalue.
+	fn capture_upvalue(&mut self, slot: usize) -> Obj {
+		let search = self
+			.open_upvalues
+			.binary_search_by_key(&slot, |upvalue| {
+				match upvalue.as_upvalue().unwrap().state() {
+					UpvalueState::Open(slot) => slot,
+					UpvalueState::Closed(_) => unreachable!(),
+				}
+			});
+
+		match search {
+			Ok(idx) => self.open_upvalues[idx],
+			Err(idx) => {
+				let upvalue = self.alloc(ObjUpvalue::new(slot));
+				self.open_upvalues.insert(idx, upvalue);
+
+				upvalue
+			}
+		}
+	}
+
+	/// Closes every open upvalue pointing at or above the given stack slot.
+	fn close_upvalues(&mut self, last: usize) {
+		while let Some(mut upvalue) = self.open_upvalues.last().copied() {
+			let upvalue = upvalue.as_upvalue_mut().unwrap();
+			match upvalue.state() {
+				UpvalueState::Open(slot) if slot >= last => {
+					upvalue.close(self.stack[slot])
+				}
+				_ => break,
+			}
+
+			self.open_upvalues.pop();
+		}
+	}
+
+	fn add(&mut self) -> anyhow::Result<()> {
+		let rhs = self.stack.pop().unwrap();
+		let lhs = self.stack.pop().unwrap();
+
+		self.disasm.write_value(&lhs);
+		self.disasm.write_value(&rhs);
+
+		let result = match (lhs, rhs) {
+			(Value::Number(lhs), Value::Number(rhs)) => Value::Number(lhs + rhs),
+			_ => match (lhs.as_str(), rhs.as_str()) {
+				(Some(lhs), Some(rhs)) => {
+					let mut chars = String::with_capacity(lhs.len() + rhs.len());
+					chars.push_str(lhs);
+					chars.push_str(rhs);
+
+					Value::Obj(self.intern_string(chars))
+				}
+				_ => {
+					return Err(Error::runtime(format!(
+						"Binary operator `+` not applicable to values `{}` and `{}`",
+						lhs, rhs,
+					))
+					.into());
+				}
+			},
+		};
+
+		self.stack.push(result);
+
+		Ok(())
+	}
+
+	fn negate(&mut self) -> anyhow::Result<()> {
+		let disasm = &self.disasm;
 		let mut result = Ok(());
-		stack.mutate(|value| {
-			self.disasm.write_value(value);
+		self.stack.mutate(|value| {
+			disasm.write_value(value);
 			match value {
 				Value::Number(n) => *n *= -1.,
 				other => {
-					result = Err(Error::Runtime(format!(
+					result = Err(Error::runtime(format!(
 						"Unary operator `-` not applicable to value: {}",
 						other,
 					)));
@@ -159,20 +880,324 @@ impl VM {
 		Ok(())
 	}
 
-	fn not(&self, stack: &mut Stack<Value>) {
-		stack.mutate(|value| *value = Value::Bool(value.is_falsy()))
+	fn not(&mut self) {
+		self.stack
+			.mutate(|value| *value = Value::Bool(value.is_falsy()))
 	}
 
-	fn equal(&self, stack: &mut Stack<Value>) {
-		let rhs = stack.pop().unwrap();
-		stack.mutate(|lhs| {
+	fn equal(&mut self) {
+		let rhs = self.stack.pop().unwrap();
+		self.stack.mutate(|lhs| {
 			*lhs = Value::Bool(*lhs == rhs);
 		});
 	}
 
-	fn return_(&self, stack: &mut Stack<Value>) {
-		if let Some(value) = stack.pop() {
-			self.disasm.write_value(&value);
+	fn print(&mut self) -> anyhow::Result<()> {
+		let value = self.stack.pop().unwrap();
+		self.disasm.write_value(&value);
+
+		writeln!(self.out, "{}", value)?;
+
+		Ok(())
+	}
+
+	fn class(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let name = self.read_name(op, frame);
+		let class = self.alloc(ObjClass::new(name));
+
+		self.stack.push(Value::Obj(class));
+	}
+
+	fn get_property(&mut self, op: OpCode, frame: &mut CallFrame) -> anyhow::Result<()> {
+		let name = self.read_name(op, frame);
+		let receiver = *self.stack.peek(0).unwrap();
+
+		let value = match receiver {
+			Value::Obj(obj) if obj.as_instance().is_some() => {
+				let instance = obj.as_instance().unwrap();
+
+				// Fields shadow methods
+				match instance.field(name) {
+					Some(value) => value,
+					None => self.bind_method(instance.class(), name, receiver)?,
+				}
+			}
+			Value::Obj(obj) if obj.as_userdata().is_some() => {
+				self.bind_method(obj.as_userdata().unwrap().class(), name, receiver)?
+			}
+			_ => {
+				return Err(Error::runtime(format!(
+					"Only instances have properties, found `{}`",
+					receiver
+				))
+				.into())
+			}
+		};
+
+		self.stack.pop();
+		self.stack.push(value);
+
+		Ok(())
+	}
+
+	fn set_property(&mut self, op: OpCode, frame: &mut CallFrame) -> anyhow::Result<()> {
+		let name = self.read_name(op, frame);
+		let receiver = *self.stack.peek(1).unwrap();
+
+		let instance = match receiver {
+			Value::Obj(obj) if obj.as_instance().is_some() => obj,
+			Value::Obj(obj) if obj.as_userdata().is_some() => {
+				return Err(Error::runtime(format!(
+					"Userdata can't have fields, found `{}`",
+					receiver
+				))
+				.into())
+			}
+			_ => {
+				return Err(Error::runtime(format!(
+					"Only instances have fields, found `{}`",
+					receiver
+				))
+				.into())
+			}
+		};
+
+		let value = self.stack.pop().unwrap();
+		self.heap.modify(instance, |instance| {
+			instance
+				.as_instance_mut()
+				.unwrap()
+				.set_field(name, value)
+		});
+
+		// Replace the instance with the assigned value
+		self.stack.pop();
+		self.stack.push(value);
+
+		Ok(())
+	}
+
+	fn method(&mut self, op: OpCode, frame: &mut CallFrame) {
+		let name = self.read_name(op, frame);
+		let method = match self.stack.pop().unwrap() {
+			Value::Obj(method) => method,
+			other => unreachable!("Expected a closure, found `{}`", other),
+		};
+
+		match *self.stack.peek(0).unwrap() {
+			Value::Obj(class) => self.heap.modify(class, |class| {
+				class
+					.as_class_mut()
+					.expect("Expected a class")
+					.set_method(name, method)
+			}),
+			other => unreachable!("Expected a class, found `{}`", other),
+		}
+	}
+
+	/// Calls the method `name` on the receiver below the arguments on the stack,
+	/// without allocating an intermediate bound method.
+	fn invoke(&mut self, name: Obj, argc: usize) -> anyhow::Result<()> {
+		let receiver = *self.stack.peek(argc).unwrap();
+		let instance = match receiver {
+			Value::Obj(obj) if obj.as_instance().is_some() => obj,
+			Value::Obj(obj) if obj.as_userdata().is_some() => {
+				return self.invoke_from_class(
+					obj.as_userdata().unwrap().class(),
+					name,
+					argc,
+				);
+			}
+			_ => {
+				return Err(Error::runtime(format!(
+					"Only instances have methods, found `{}`",
+					receiver
+				))
+				.into())
+			}
+		};
+		let instance = instance.as_instance().unwrap();
+
+		// A field holding a callable value takes precedence over a method
+		if let Some(field) = instance.field(name) {
+			let base = self.stack.size() - argc - 1;
+			self.stack[base] = field;
+
+			return self.call_value(argc);
+		}
+
+		self.invoke_from_class(instance.class(), name, argc)
+	}
+
+	fn invoke_from_class(
+		&mut self,
+		class: &ObjClass,
+		name: Obj,
+		argc: usize,
+	) -> anyhow::Result<()> {
+		match class.method(name) {
+			Some(method) => self.call_method(method, argc),
+			None => Err(Error::runtime(format!("Undefined property `{}`", name)).into()),
+		}
+	}
+
+	/// Looks up the method `name` on `class` and binds it to `receiver`.
+	fn bind_method(
+		&mut self,
+		class: &ObjClass,
+		name: Obj,
+		receiver: Value,
+	) -> anyhow::Result<Value> {
+		match class.method(name) {
+			Some(method) => {
+				let bound = self.alloc(ObjBoundMethod::new(receiver, method));
+				Ok(Value::Obj(bound))
+			}
+			None => Err(Error::runtime(format!("Undefined property `{}`", name)).into()),
 		}
 	}
+
+	fn inherit(&mut self) -> anyhow::Result<()> {
+		let superclass = match *self.stack.peek(1).unwrap() {
+			Value::Obj(obj) if obj.as_class().is_some() => obj,
+			other => {
+				return Err(Error::runtime(format!(
+					"Superclass must be a class, found `{}`",
+					other
+				))
+				.into())
+			}
+		};
+
+		match self.stack.pop().unwrap() {
+			Value::Obj(subclass) => self.heap.modify(subclass, |subclass| {
+				subclass
+					.as_class_mut()
+					.expect("Expected a class")
+					.inherit(superclass.as_class().unwrap())
+			}),
+			other => unreachable!("Expected a class, found `{}`", other),
+		}
+
+		Ok(())
+	}
+
+	fn get_super(&mut self, op: OpCode, frame: &mut CallFrame) -> anyhow::Result<()> {
+		let name = self.read_name(op, frame);
+		let superclass = self.pop_superclass();
+		let receiver = self.stack.pop().unwrap();
+
+		let method = self.bind_method(superclass.as_class().unwrap(), name, receiver)?;
+		self.stack.push(method);
+
+		Ok(())
+	}
+
+	// The superclass is pushed by the compiler above the receiver (and arguments, for
+	// `SuperInvoke`), so it should always be a class
+	fn pop_superclass(&mut self) -> Obj {
+		match self.stack.pop().unwrap() {
+			Value::Obj(obj) if obj.as_class().is_some() => obj,
+			other => unreachable!("Expected a class, found `{}`", other),
+		}
+	}
+
+	fn call_value(&mut self, argc: usize) -> anyhow::Result<()> {
+		let callee = *self.stack.peek(argc).unwrap();
+		let base = self.stack.size() - argc - 1;
+
+		match callee {
+			Value::Obj(obj) if obj.as_closure().is_some() => self.call_closure(obj, argc),
+			// Calling a class creates a new instance, which replaces the class on the
+			// stack and becomes `this` for the initializer (if any)
+			Value::Obj(obj) if obj.as_class().is_some() => {
+				let instance = self.alloc(ObjInstance::new(obj));
+				self.stack[base] = Value::Obj(instance);
+
+				match obj.as_class().unwrap().method(self.init_string) {
+					Some(init) => self.call_closure(init, argc),
+					None if argc != 0 => Err(Error::runtime(format!(
+						"Expected 0 arguments but got {}.",
+						argc
+					))
+					.into()),
+					None => Ok(()),
+				}
+			}
+			Value::Obj(obj) if obj.as_bound_method().is_some() => {
+				let bound = obj.as_bound_method().unwrap();
+				self.stack[base] = bound.receiver();
+
+				self.call_method(bound.method(), argc)
+			}
+			Value::Obj(obj) if obj.as_native().is_some() => {
+				self.call_native(obj.as_native().unwrap(), argc, false)
+			}
+			_ => Err(Error::runtime(format!(
+				"Can only call functions and classes, found `{}`",
+				callee
+			))
+			.into()),
+		}
+	}
+
+	/// Calls a method with its receiver in the callee's slot on the stack.
+	fn call_method(&mut self, method: Obj, argc: usize) -> anyhow::Result<()> {
+		match method.as_native() {
+			Some(native) => self.call_native(native, argc, true),
+			None => self.call_closure(method, argc),
+		}
+	}
+
+	/// Calls a native directly, replacing the callee and its arguments on the stack
+	/// with the result. Natives called as methods also get their receiver as the first
+	/// argument.
+	fn call_native(
+		&mut self,
+		native: &ObjNative,
+		argc: usize,
+		is_method: bool,
+	) -> anyhow::Result<()> {
+		if argc != native.arity() as usize {
+			return Err(Error::runtime(format!(
+				"Expected {} arguments but got {}.",
+				native.arity(),
+				argc,
+			))
+			.into());
+		}
+
+		// The arguments stay on the stack (and rooted) until the native returns
+		let args = self.stack.top(argc + is_method as usize).to_vec();
+		let host_values = self.host_values.len();
+		let result = (native.function())(self, &args);
+
+		// Anything the native created is either part of its result or garbage by now
+		self.host_values.truncate(host_values);
+		let result = result?;
+
+		self.stack.truncate(self.stack.size() - argc - 1);
+		self.stack.push(result);
+
+		Ok(())
+	}
+
+	fn call_closure(&mut self, closure: Obj, argc: usize) -> anyhow::Result<()> {
+		let arity = closure.as_closure().unwrap().function().arity();
+		if argc != arity as usize {
+			return Err(Error::runtime(format!(
+				"Expected {} arguments but got {}.",
+				arity, argc,
+			))
+			.into());
+		}
+		if self.frames.len() == Self::FRAMES_MAX {
+			return Err(Error::runtime("Stack overflow.").into());
+		}
+
+		let base = self.stack.size() - argc - 1;
+		self.frames.push(CallFrame::new(closure, base));
+
+		Ok(())
+	}
 }
@@ -0,0 +1,159 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+	repr::{ObjKind, Value},
+	vm::{error::Error, VM},
+};
+
+/// A function implemented in Rust, called with the arguments from the top of the
+/// stack. The VM checks the number of arguments against the native's arity before
+/// calling it.
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+
+/// Everything needed to define a native as a global, as generated by the `#[native]`
+/// attribute (see `VM::register_native`).
+#[derive(Clone, Copy)]
+pub struct Native {
+	pub name: &'static str,
+	pub arity: u8,
+	pub function: NativeFn,
+}
+
+impl Native {
+	const fn new(name: &'static str, arity: u8, function: NativeFn) -> Self {
+		Self {
+			name,
+			arity,
+			function,
+		}
+	}
+}
+
+/// The natives defined as globals in every VM
+pub(super) const STDLIB: &[Native] = &[
+	Native::new("clock", 0, clock),
+	Native::new("str", 1, str),
+	Native::new("num", 1, num),
+	Native::new("len", 1, len),
+	Native::new("type", 1, type_),
+	Native::new("input", 0, input),
+	Native::new("exit", 1, exit),
+	Native::new("argc", 0, argc),
+	Native::new("argv", 1, argv),
+];
+
+/// Milliseconds since the Unix epoch
+fn clock(_: &mut VM, _: &[Value]) -> Result<Value, Error> {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.expect("System time is before the Unix epoch");
+
+	Ok(Value::Number(now.as_millis() as f64))
+}
+
+fn str(vm: &mut VM, args: &[Value]) -> Result<Value, Error> {
+	match args[0] {
+		Value::Obj(obj) if obj.as_string().is_some() => Ok(args[0]),
+		other => Ok(Value::Obj(vm.intern(other.to_string()))),
+	}
+}
+
+fn num(_: &mut VM, args: &[Value]) -> Result<Value, Error> {
+	let parsed = match args[0] {
+		Value::Number(_) => return Ok(args[0]),
+		other => other
+			.as_str()
+			.and_then(|s| s.trim().parse::<f64>().ok()),
+	};
+
+	parsed
+		.map(Value::Number)
+		.ok_or_else(|| Error::runtime(format!("Can't convert `{}` to a number", args[0])))
+}
+
+fn len(_: &mut VM, args: &[Value]) -> Result<Value, Error> {
+	match args[0].as_str() {
+		Some(chars) => Ok(Value::Number(chars.chars().count() as f64)),
+		None => Err(Error::runtime(format!(
+			"`len` expects a string, found `{}`",
+			args[0]
+		))),
+	}
+}
+
+fn type_(vm: &mut VM, args: &[Value]) -> Result<Value, Error> {
+	let name = match args[0] {
+		Value::Number(_) => "number",
+		Value::Bool(_) => "bool",
+		Value::Nil => "nil",
+		Value::Obj(obj) => match obj.kind() {
+			ObjKind::String => "string",
+			ObjKind::Function
+			| ObjKind::Closure
+			| ObjKind::BoundMethod
+			| ObjKind::Native => "function",
+			ObjKind::Class => "class",
+			ObjKind::Instance | ObjKind::Userdata => "instance",
+			ObjKind::Upvalue => unreachable!("Upvalues aren't first-class values"),
+		},
+	};
+
+	Ok(Value::Obj(vm.intern(name.into())))
+}
+
+/// Reads a line from the VM's input, without the line ending, or returns `nil` at
+/// the end of the input.
+fn input(vm: &mut VM, _: &[Value]) -> Result<Value, Error> {
+	let mut line = String::new();
+	let read = vm
+		.read_line(&mut line)
+		.map_err(|err| Error::runtime(format!("Failed to read input: {}", err)))?;
+
+	if read == 0 {
+		return Ok(Value::Nil);
+	}
+	if line.ends_with('\n') {
+		line.pop();
+		if line.ends_with('\r') {
+			line.pop();
+		}
+	}
+
+	Ok(Value::Obj(vm.intern(line)))
+}
+
+fn exit(_: &mut VM, args: &[Value]) -> Result<Value, Error> {
+	match args[0] {
+		Value::Number(code) if code.fract() == 0. => Err(Error::Exit(code as i32)),
+		other => Err(Error::runtime(format!(
+			"`exit` expects an integer exit code, found `{}`",
+			other
+		))),
+	}
+}
+
+/// The number of command-line arguments passed to the script
+fn argc(vm: &mut VM, _: &[Value]) -> Result<Value, Error> {
+	Ok(Value::Number(vm.args.len() as f64))
+}
+
+/// The command-line argument at the given index, or `nil` if there aren't that many
+fn argv(vm: &mut VM, args: &[Value]) -> Result<Value, Error> {
+	let idx = match args[0] {
+		Value::Number(idx) if idx.fract() == 0. && idx >= 0. => idx as usize,
+		other => {
+			return Err(Error::runtime(format!(
+				"`argv` expects a non-negative integer index, found `{}`",
+				other
+			)))
+		}
+	};
+
+	match vm.args.get(idx) {
+		Some(arg) => {
+			let arg = arg.clone();
+			Ok(Value::Obj(vm.intern(arg)))
+		}
+		None => Ok(Value::Nil),
+	}
+}
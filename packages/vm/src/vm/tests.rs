@@ -0,0 +1,1366 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+use crate::{
+	repr::{Trace, Tracer, Userdata, Value},
+	stack::Stack,
+};
+
+use super::{
+	Code, ConversionError, Error, FromLox, Native, Options, Severity, TraceFrame, VM,
+};
+
+/// Shared buffer for capturing the output of `print` statements
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for Output {
+	fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+		self.0.borrow_mut().write(bytes)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Output {
+	fn take(&self) -> String {
+		String::from_utf8(self.0.take()).unwrap()
+	}
+}
+
+fn vm() -> (VM, Output) {
+	// Collect garbage on every allocation, to catch any objects that aren't
+	// properly rooted
+	let mut vm = VM::with_options(Options { stress_gc: true });
+	let output = Output::default();
+	vm.set_output(output.clone());
+
+	(vm, output)
+}
+
+fn run(src: &str) -> String {
+	let (mut vm, output) = vm();
+	vm.interpret(src.into()).unwrap();
+
+	output.take()
+}
+
+#[test]
+fn print_statements() {
+	let output = run(r#"
+		print 1 + 2;
+		print "foo" + "bar";
+		print nil;
+		print !true;
+	"#);
+
+	assert_eq!(output, "3\nfoobar\nnil\nfalse\n");
+}
+
+#[test]
+fn string_equality() {
+	let output = run(r#"
+		print "cat" != "dog";
+		print "a" + "b" == "ab";
+		print "" == "";
+	"#);
+
+	assert_eq!(output, "true\ntrue\ntrue\n");
+}
+
+#[test]
+fn expression_statements_leave_the_stack_empty() {
+	let (mut vm, output) = vm();
+	vm.interpret(r#"1 + 2; "foo"; nil;"#.into())
+		.unwrap();
+
+	assert_eq!(vm.stack.size(), 0);
+	assert_eq!(output.take(), "");
+}
+
+#[test]
+fn comments_are_ignored() {
+	let output = run(r#"
+		// print "nope";
+		print "// not a comment"; // but this is
+	"#);
+
+	assert_eq!(output, "// not a comment\n");
+}
+
+#[test]
+fn missing_semicolon() {
+	let (mut vm, _) = vm();
+	assert!(vm.interpret("print 1".into()).is_err());
+	assert!(vm.interpret("1 + 2".into()).is_err());
+}
+
+#[test]
+fn runtime_errors_reset_the_stack() {
+	let (mut vm, output) = vm();
+	assert!(vm
+		.interpret(r#"print 1 + -"foo";"#.into())
+		.is_err());
+	assert!(vm
+		.interpret(r#"print "foo" + 1;"#.into())
+		.is_err());
+
+	vm.interpret("print 42;".into()).unwrap();
+	assert_eq!(output.take(), "42\n");
+}
+
+#[test]
+fn statements_example() {
+	let output = run(include_str!("../../../spec/src/examples/statements.lox"));
+
+	assert_eq!(output, "Hello, world!\ntrue\n3\nfoobar\nfalse\nnil\n");
+}
+
+#[test]
+fn global_variables() {
+	let output = run(r#"
+		var beverage = "cafe au lait";
+		var breakfast = "beignets with " + beverage;
+		var nothing;
+		print breakfast;
+		print nothing;
+	"#);
+
+	assert_eq!(output, "beignets with cafe au lait\nnil\n");
+}
+
+#[test]
+fn global_assignment() {
+	let output = run(r#"
+		var a = 1;
+		var b;
+		a = b = a + 1;
+		print a;
+		print b;
+		print a = "three";
+	"#);
+
+	assert_eq!(output, "2\n2\nthree\n");
+}
+
+#[test]
+fn undefined_variables() {
+	let (mut vm, output) = vm();
+	assert!(vm.interpret("print nope;".into()).is_err());
+	assert!(vm.interpret("nope = 1;".into()).is_err());
+
+	// The failed assignment shouldn't have implicitly declared the variable
+	assert!(vm.interpret("print nope;".into()).is_err());
+	assert_eq!(output.take(), "");
+}
+
+#[test]
+fn invalid_assignment_target() {
+	let (mut vm, _) = vm();
+	vm.interpret("var a; var b;".into()).unwrap();
+
+	assert!(vm.interpret("a + b = 1;".into()).is_err());
+	assert!(vm.interpret("-a = 1;".into()).is_err());
+	assert!(vm.interpret("(a) = 1;".into()).is_err());
+}
+
+#[test]
+fn globals_persist_between_calls() {
+	let (mut vm, output) = vm();
+	vm.interpret("var x = 1;".into()).unwrap();
+	vm.interpret("x = x + 1;".into()).unwrap();
+	vm.interpret("print x;".into()).unwrap();
+
+	assert_eq!(output.take(), "2\n");
+}
+
+#[test]
+fn local_variables() {
+	let output = run(r#"
+		var a = "global";
+		{
+			var a = "outer";
+			var b = a + " b";
+			{
+				var a = "inner";
+				print a;
+				a = "reassigned";
+				print a;
+			}
+			print a;
+			print b;
+		}
+		print a;
+	"#);
+
+	assert_eq!(output, "inner\nreassigned\nouter\nouter b\nglobal\n");
+}
+
+#[test]
+fn blocks_leave_the_stack_empty() {
+	let (mut vm, output) = vm();
+	vm.interpret("{ var a = 1; var b = 2; { var c = a + b; print c; } }".into())
+		.unwrap();
+
+	assert_eq!(vm.stack.size(), 0);
+	assert_eq!(output.take(), "3\n");
+}
+
+#[test]
+fn scope_example() {
+	let output = run(include_str!("../../../spec/src/examples/scope.lox"));
+
+	assert_eq!(
+		output,
+		"inner a\nouter b\nglobal c\nouter a\nouter b\nglobal c\nglobal a\nglobal b\nglobal c\n"
+	);
+}
+
+#[test]
+fn local_resolution_errors() {
+	let (mut vm, _) = vm();
+
+	let err = vm
+		.interpret(r#"{ var foo = "foo"; var foo = "bar"; }"#.into())
+		.unwrap_err();
+	assert!(
+		format!("{}", err).contains("Already a variable with this name in this scope.")
+	);
+
+	let err = vm
+		.interpret(r#"var bar = "bar"; { var bar = bar; }"#.into())
+		.unwrap_err();
+	assert!(
+		format!("{}", err).contains("Can't read local variable in its own initializer.")
+	);
+
+	// Shadowing is fine in a nested scope, and redeclaring globals is fine
+	vm.interpret(r#"{ var foo; { var foo; } } var baz; var baz;"#.into())
+		.unwrap();
+}
+
+#[test]
+fn unterminated_block() {
+	let (mut vm, _) = vm();
+	assert!(vm.interpret("{ var a = 1;".into()).is_err());
+}
+
+#[test]
+fn if_else() {
+	let output = run(r#"
+		if (true) print "then"; else print "else";
+		if (nil) print "then"; else print "else";
+		if (false) print "skipped";
+		if (1 < 2) {
+			var a = "block";
+			print a;
+		}
+	"#);
+
+	assert_eq!(output, "then\nelse\nblock\n");
+}
+
+#[test]
+fn logical_operators() {
+	let output = run(r#"
+		print true and "rhs";
+		print false and "rhs";
+		print nil or "rhs";
+		print "lhs" or "rhs";
+		print 1 < 2 and 3 < 4 or false;
+
+		var called = false;
+		false and (called = true);
+		true or (called = true);
+		print called;
+	"#);
+
+	assert_eq!(output, "rhs\nfalse\nrhs\nlhs\ntrue\nfalse\n");
+}
+
+#[test]
+fn conditionals_example() {
+	let output = run(include_str!("../../../spec/src/examples/conditionals.lox"));
+
+	assert_eq!(output, "bar\nbar\nbar\nbazbar\n");
+}
+
+#[test]
+fn while_loops() {
+	let output = run(include_str!("../../../spec/src/examples/while.lox"));
+	let expected = (0..10)
+		.map(|i| format!("{}\n", i))
+		.collect::<String>();
+
+	assert_eq!(output, expected);
+}
+
+#[test]
+fn for_loops() {
+	let output = run(include_str!("../../../spec/src/examples/forloop.lox"));
+	let expected = (0..100)
+		.map(|i| format!("{}\n", i))
+		.collect::<String>();
+
+	assert_eq!(output, expected);
+
+	let output = run(r#"
+		var i = 0;
+		for (; i < 3;) i = i + 1;
+		print i;
+
+		for (var j = 0; j < 2; j = j + 1) {
+			var k = j * 10;
+			print k;
+		}
+	"#);
+
+	assert_eq!(output, "3\n0\n10\n");
+}
+
+#[test]
+fn fizzbuzz_example() {
+	let output = run(include_str!("../../../spec/src/examples/fizzbuzz1.lox"));
+	let expected = (0..100)
+		.map(|i| match (i % 3, i % 5) {
+			(_, _) if i == 0 => "0\n".to_string(),
+			(0, 0) => "fizzbuzz\n".to_string(),
+			(_, 0) => "buzz\n".to_string(),
+			(0, _) => "fizz\n".to_string(),
+			_ => format!("{}\n", i),
+		})
+		.collect::<String>();
+
+	assert_eq!(output, expected);
+}
+
+#[test]
+fn function_declaration_example() {
+	let output = run(include_str!(
+		"../../../spec/src/examples/function-declaration.lox"
+	));
+
+	assert_eq!(output, "Hello, world!\n");
+}
+
+#[test]
+fn functions() {
+	let output = run(r#"
+		fun printSum(a, b) {
+			print a + b;
+		}
+		fun returnSum(a, b) {
+			return a + b;
+		}
+		fun noReturn() {}
+
+		printSum(400, 20);
+		print returnSum(60, 9);
+		print noReturn();
+		print returnSum;
+	"#);
+
+	assert_eq!(output, "420\n69\nnil\n<fn returnSum>\n");
+}
+
+#[test]
+fn functions_are_first_class() {
+	let output = run(r#"
+		fun addPairs(a, b) {
+			return a + b;
+		}
+		fun identity(a) {
+			return a;
+		}
+		print identity(addPairs)(1, 2);
+
+		fun outerFunction() {
+			fun localFunction() {
+				print "I'm local!";
+			}
+			localFunction();
+		}
+		outerFunction();
+	"#);
+
+	assert_eq!(output, "3\nI'm local!\n");
+}
+
+#[test]
+fn recursion() {
+	let output = run(r#"
+		fun fib(n) {
+			if (n <= 1) return n;
+			return fib(n - 2) + fib(n - 1);
+		}
+		for (var i = 0; i < 10; i = i + 1) print fib(i);
+	"#);
+
+	assert_eq!(output, "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n");
+}
+
+#[test]
+fn call_errors() {
+	let (mut vm, output) = vm();
+	vm.interpret("fun f(a, b) { return a; }".into())
+		.unwrap();
+
+	let err = vm.interpret("f(1);".into()).unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Expected 2 arguments but got 1.\n  |\n1 | f(1);\n  |    ^\n[line 1] in script"
+	);
+
+	assert!(vm
+		.interpret(r#""not a function"();"#.into())
+		.is_err());
+	assert!(vm.interpret("nil();".into()).is_err());
+
+	let err = vm
+		.interpret("fun forever() { forever(); } forever();".into())
+		.unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Stack overflow.\n  |\n1 | fun forever() { forever(); } forever();\n  |                         ^\n[line 1] in forever()\n... repeated 62 more times\n[line 1] in script"
+	);
+
+	// The VM should still be usable after unwinding from an error
+	vm.interpret("print f(42, 0);".into()).unwrap();
+	assert_eq!(output.take(), "42\n");
+}
+
+#[test]
+fn stack_overflow_from_wide_frames() {
+	let (mut vm, output) = vm();
+
+	// Fewer frames than the call depth limit, but too many slots for the stack
+	let params = (1..255)
+		.map(|i| format!("a{}", i))
+		.collect::<Vec<_>>()
+		.join(", ");
+	let nils = vec!["nil"; 254].join(", ");
+	let src = format!(
+		"fun f(n, {0}) {{ if (n == 0) return 0; return 1 + 1 + 1 + 1 + f(n - 1, {0}); }}
+print f(100, {1});",
+		params, nils
+	);
+
+	let err = vm.interpret(src).unwrap_err();
+	assert!(err
+		.to_string()
+		.starts_with("RuntimeError: Stack overflow."));
+
+	// The host can't overflow the stack with arguments either
+	vm.interpret("fun g() {}".into()).unwrap();
+	let g = vm.global("g").unwrap();
+	let args = vec![Value::Nil; Stack::<Value>::MAX];
+	let err = vm.call(g, &args).unwrap_err();
+	assert_eq!(err.to_string(), "RuntimeError: Stack overflow.");
+
+	vm.interpret("print 1;".into()).unwrap();
+	assert_eq!(output.take(), "1\n");
+}
+
+#[test]
+fn top_level_return() {
+	let (mut vm, _) = vm();
+	let err = vm
+		.interpret(r#"return "from top level";"#.into())
+		.unwrap_err();
+
+	assert!(format!("{}", err).contains("Can't return from top-level code."));
+}
+
+#[test]
+fn counter_example() {
+	let output = run(include_str!("../../../spec/src/examples/counter.lox"));
+	let expected = (1..=100)
+		.map(|i| format!("{}\n", i))
+		.collect::<String>();
+
+	assert_eq!(output, expected);
+}
+
+#[test]
+fn function_expression_example() {
+	let output = run(include_str!(
+		"../../../spec/src/examples/function-expression.lox"
+	));
+
+	assert_eq!(output, "IIFE!\n0\n1\n2\n");
+}
+
+#[test]
+fn closures_share_captured_variables() {
+	let output = run(r#"
+		var get;
+		var set;
+		{
+			var a = "initial";
+			fun getter() { return a; }
+			fun setter(value) { a = value; }
+			get = getter;
+			set = setter;
+		}
+		print get();
+		set("updated");
+		print get();
+	"#);
+
+	assert_eq!(output, "initial\nupdated\n");
+}
+
+#[test]
+fn closures_capture_through_enclosing_functions() {
+	let output = run(r#"
+		fun outer() {
+			var x = "outside";
+			fun middle() {
+				fun inner() {
+					print x;
+				}
+				return inner;
+			}
+			return middle;
+		}
+		outer()()();
+	"#);
+
+	assert_eq!(output, "outside\n");
+}
+
+#[test]
+fn loop_variables_are_closed_per_scope() {
+	let output = run(r#"
+		var first;
+		var second;
+		for (var i = 0; i < 2; i = i + 1) {
+			var j = i;
+			fun show() { print j; }
+			if (first == nil) first = show;
+			else second = show;
+		}
+		first();
+		second();
+	"#);
+
+	assert_eq!(output, "0\n1\n");
+}
+
+#[test]
+fn anonymous_functions() {
+	let output = run(r#"
+		var add = fun (a, b) { return a + b; };
+		print add(1, 2);
+		print add;
+	"#);
+
+	assert_eq!(output, "3\n<fn (anonymous)>\n");
+}
+
+#[test]
+fn classes_example() {
+	let output = run(include_str!("../../../spec/src/examples/classes.lox"));
+	let expected = "\
+		Breakfast\n\
+		Breakfast instance\n\
+		Enjoy your bacon and toast, Dear Reader.\n\
+		Breakfast instance\n\
+		Enjoy your ham and bagels, ya filthy animal.\n\
+		Enjoy your ham and English muffin, Noble Reader.\n\
+		How about a Bloody Mary to wash it down?\n";
+
+	assert_eq!(output, expected);
+}
+
+#[test]
+fn fields_and_methods() {
+	let output = run(r#"
+		class Pair {
+			sum() { return this.a + this.b; }
+		}
+		var pair = Pair();
+		pair.a = 1;
+		pair.b = 2;
+		print pair.sum();
+
+		var method = pair.sum;
+		pair.b = 40;
+		print method();
+		print method;
+	"#);
+
+	assert_eq!(output, "3\n41\n<fn sum>\n");
+}
+
+#[test]
+fn fields_shadow_methods() {
+	let output = run(r#"
+		class Thing {
+			method() { return "method"; }
+		}
+		fun field() { return "field"; }
+
+		var thing = Thing();
+		print thing.method();
+		thing.method = field;
+		print thing.method();
+	"#);
+
+	assert_eq!(output, "method\nfield\n");
+}
+
+#[test]
+fn initializers() {
+	let output = run(r#"
+		class Point {
+			init(x, y) {
+				this.x = x;
+				this.y = y;
+				if (x == 0) return;
+				this.y = y * 2;
+			}
+		}
+		var a = Point(0, 2);
+		var b = Point(1, 2);
+		print a.y;
+		print b.y;
+		print b.init(3, 4) == b;
+		print b.x;
+	"#);
+
+	assert_eq!(output, "2\n4\ntrue\n3\n");
+}
+
+#[test]
+fn closures_capture_this() {
+	let output = run(r#"
+		class Greeter {
+			init(name) { this.name = name; }
+			greeter() {
+				fun greet() { print "Hello, " + this.name + "!"; }
+				return greet;
+			}
+		}
+		Greeter("world").greeter()();
+	"#);
+
+	assert_eq!(output, "Hello, world!\n");
+}
+
+#[test]
+fn class_errors() {
+	let (mut vm, _) = vm();
+
+	let err = vm.interpret("print this;".into()).unwrap_err();
+	assert!(format!("{}", err).contains("Can't use `this` outside of a method."));
+
+	let err = vm
+		.interpret("class A { init() { return 1; } }".into())
+		.unwrap_err();
+	assert!(format!("{}", err).contains("Can't return a value from an initializer."));
+
+	vm.interpret("class B {} var b = B();".into())
+		.unwrap();
+
+	let err = vm.interpret("b.missing;".into()).unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Undefined property `missing`\n  |\n1 | b.missing;\n  |   ^^^^^^^\n[line 1] in script"
+	);
+
+	let err = vm.interpret("b.missing();".into()).unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Undefined property `missing`\n  |\n1 | b.missing();\n  |   ^^^^^^^\n[line 1] in script"
+	);
+
+	let err = vm.interpret("B(1);".into()).unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Expected 0 arguments but got 1.\n  |\n1 | B(1);\n  |    ^\n[line 1] in script"
+	);
+
+	let err = vm
+		.interpret("var n = 1; n.x = 2;".into())
+		.unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Only instances have fields, found `1`\n  |\n1 | var n = 1; n.x = 2;\n  |              ^\n[line 1] in script"
+	);
+
+	let err = vm.interpret(r#""str".len;"#.into()).unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Only instances have properties, found `str`\n  |\n1 | \"str\".len;\n  |       ^^^\n[line 1] in script"
+	);
+}
+
+#[test]
+fn linked_list_example() {
+	let output = run(include_str!("../../../spec/src/examples/linked-list.lox"));
+
+	assert!(output.starts_with("Squares:\n0\n1\n4\n9\n"), "{}", output);
+	assert!(output.contains("Sum of squares:\n"), "{}", output);
+}
+
+#[test]
+fn inheritance() {
+	let output = run(r#"
+		class A {
+			method() { print "A method"; }
+			other() { print "A other"; }
+		}
+		class B < A {
+			method() { print "B method"; }
+			test() {
+				super.method();
+				var method = super.other;
+				method();
+			}
+		}
+		class C < B {}
+
+		C().test();
+		C().method();
+	"#);
+
+	assert_eq!(output, "A method\nA other\nB method\n");
+}
+
+#[test]
+fn super_in_closures() {
+	let output = run(r#"
+		class Base {
+			greet() { return "Base"; }
+		}
+		class Derived < Base {
+			greeter() {
+				fun greet() { return super.greet() + " via Derived"; }
+				return greet;
+			}
+		}
+		print Derived().greeter()();
+	"#);
+
+	assert_eq!(output, "Base via Derived\n");
+}
+
+#[test]
+fn inheritance_errors() {
+	let (mut vm, _) = vm();
+
+	let err = vm.interpret("class A < A {}".into()).unwrap_err();
+	assert!(format!("{}", err).contains("A class can't inherit from itself."));
+
+	let err = vm.interpret("super.foo();".into()).unwrap_err();
+	assert!(format!("{}", err).contains("Can't use `super` outside of a class."));
+
+	let err = vm
+		.interpret("class A { method() { super.method(); } }".into())
+		.unwrap_err();
+	assert!(
+		format!("{}", err).contains("Can't use `super` in a class with no superclass.")
+	);
+
+	let err = vm
+		.interpret("fun f() { return this; }".into())
+		.unwrap_err();
+	assert!(format!("{}", err).contains("Can't use `this` outside of a method."));
+
+	let err = vm
+		.interpret("var NotAClass = 1; class B < NotAClass {}".into())
+		.unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Superclass must be a class, found `1`\n  |\n1 | var NotAClass = 1; class B < NotAClass {}\n  |                              ^^^^^^^^^\n[line 1] in script"
+	);
+}
+
+#[test]
+fn objects_survive_collections() {
+	let output = run(r#"
+		class Node {
+			init(value, next) {
+				this.value = value;
+				this.next = next;
+			}
+		}
+
+		fun build(n) {
+			var list = nil;
+			for (var i = 0; i < n; i = i + 1) {
+				// Plenty of garbage along the way
+				var label = "node " + "label";
+				list = Node(i, list);
+			}
+			return list;
+		}
+
+		var list = build(20);
+		var sum = 0;
+		while (list != nil) {
+			sum = sum + list.value;
+			list = list.next;
+		}
+		print sum;
+	"#);
+
+	assert_eq!(output, "190\n");
+}
+
+#[test]
+fn native_functions() {
+	let output = run(r#"
+		print str(1.5) + str(true) + str(nil);
+		print num("42") + num(" 8 ");
+		print len("hello") + len("");
+		print type(1) + " " + type("") + " " + type(nil) + " " + type(false);
+		print type(clock) + " " + type(type);
+		print clock() > 0;
+		print clock;
+
+		class Foo {}
+		print type(Foo) + " " + type(Foo());
+	"#);
+
+	assert_eq!(
+		output,
+		"1.5truenil\n50\n5\nnumber string nil bool\nfunction function\ntrue\n<native fn clock>\nclass instance\n"
+	);
+}
+
+#[test]
+fn native_input() {
+	let (mut vm, output) = vm();
+	vm.set_input(io::Cursor::new("first\r\nsecond\n"));
+
+	vm.interpret(
+		r#"
+		print input();
+		print input();
+		print input();
+	"#
+		.into(),
+	)
+	.unwrap();
+
+	assert_eq!(output.take(), "first\nsecond\nnil\n");
+}
+
+#[test]
+fn native_args() {
+	let (mut vm, output) = vm();
+
+	vm.interpret("print argc(); print argv(0);".into())
+		.unwrap();
+	assert_eq!(output.take(), "0\nnil\n");
+
+	vm.set_args(vec!["one".into(), "two words".into()]);
+	vm.interpret(
+		r#"
+		for (var i = 0; i < argc(); i = i + 1) {
+			print argv(i);
+		}
+	"#
+		.into(),
+	)
+	.unwrap();
+	assert_eq!(output.take(), "one\ntwo words\n");
+
+	let err = vm.interpret("argv(-1);".into()).unwrap_err();
+	assert!(format!("{}", err).contains("`argv` expects a non-negative integer index"));
+}
+
+#[test]
+fn native_errors() {
+	let (mut vm, _) = vm();
+
+	let err = vm
+		.interpret("print 1;\nprint len(42);".into())
+		.unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: `len` expects a string, found `42`\n  |\n2 | print len(42);\n  |             ^\n[line 2] in script"
+	);
+
+	let err = vm
+		.interpret(r#"num("forty-two");"#.into())
+		.unwrap_err();
+	assert_eq!(
+		format!("{}", err),
+		"RuntimeError: Can't convert `forty-two` to a number\n  |\n1 | num(\"forty-two\");\n  |                ^\n[line 1] in script"
+	);
+
+	let err = vm.interpret("clock(1);".into()).unwrap_err();
+	assert!(format!("{}", err).contains("Expected 0 arguments but got 1."));
+}
+
+#[test]
+fn native_exit() {
+	let (mut vm, output) = vm();
+
+	let err = vm
+		.interpret("print 1; exit(3); print 2;".into())
+		.unwrap_err();
+	assert!(matches!(err.downcast_ref(), Some(Error::Exit(3))));
+	assert_eq!(output.take(), "1\n");
+
+	// The VM is still usable afterwards
+	vm.interpret("print 2;".into()).unwrap();
+	assert_eq!(output.take(), "2\n");
+}
+
+#[test]
+fn vms_are_independent() {
+	let (mut first, first_output) = vm();
+	let (mut second, second_output) = vm();
+
+	first
+		.interpret(r#"var name = "first";"#.into())
+		.unwrap();
+	second
+		.interpret(r#"var name = "second";"#.into())
+		.unwrap();
+	first.interpret("print name;".into()).unwrap();
+	second.interpret("print name;".into()).unwrap();
+
+	assert_eq!(first_output.take(), "first\n");
+	assert_eq!(second_output.take(), "second\n");
+
+	drop(first);
+	second.interpret("print name;".into()).unwrap();
+	assert_eq!(second_output.take(), "second\n");
+}
+
+#[test]
+fn host_calls() {
+	let (mut vm, output) = vm();
+	vm.interpret(
+		r#"
+		fun add(a, b) { return a + b; }
+
+		var count = 0;
+		fun makeCounter() {
+			fun counter() {
+				count = count + 1;
+				print count;
+				return count;
+			}
+			return counter;
+		}
+
+		class Pair {
+			init(a, b) { this.a = a; this.b = b; }
+		}
+	"#
+		.into(),
+	)
+	.unwrap();
+
+	let add = vm.global("add").unwrap();
+	let sum = vm.call(add, &[1.0.into(), 2.0.into()]).unwrap();
+	assert_eq!(sum, Value::Number(3.));
+
+	let foo = vm.intern("foo".into());
+	let bar = vm.intern("bar".into());
+	let joined = vm.call(add, &[foo.into(), bar.into()]).unwrap();
+	assert_eq!(vm.str(joined), Some("foobar"));
+
+	let make_counter = vm.global("makeCounter").unwrap();
+	let counter = vm.call(make_counter, &[]).unwrap();
+	vm.call(counter, &[]).unwrap();
+	assert_eq!(vm.call(counter, &[]).unwrap(), Value::Number(2.));
+	assert_eq!(output.take(), "1\n2\n");
+
+	let pair = vm.global("Pair").unwrap();
+	let pair = vm.call(pair, &[true.into(), Value::Nil]).unwrap();
+	assert_eq!(pair.to_string(), "Pair instance");
+
+	let len = vm.global("len").unwrap();
+	let hello = vm.intern("hello".into());
+	assert_eq!(vm.call(len, &[hello.into()]).unwrap(), Value::Number(5.));
+
+	assert!(vm.global("missing").is_none());
+
+	// The VM is still usable by scripts afterwards
+	vm.interpret("print add(count, 40);".into())
+		.unwrap();
+	assert_eq!(output.take(), "42\n");
+}
+
+#[test]
+fn host_call_errors() {
+	let (mut vm, _) = vm();
+	vm.interpret(
+		r#"
+		fun inner(n) {
+			return n + nil;
+		}
+
+		fun outer(n) {
+			var result =
+				inner(n);
+			return result;
+		}
+	"#
+		.into(),
+	)
+	.unwrap();
+
+	let outer = vm.global("outer").unwrap();
+	let err = match vm.call(outer, &[1.0.into()]) {
+		Err(Error::Runtime(err)) => err,
+		other => panic!("Expected a runtime error, found {:?}", other),
+	};
+
+	assert!(err.message.contains("Binary operator `+`"));
+	assert_eq!(err.trace, vec![
+		TraceFrame {
+			function: Some("inner".into()),
+			line: 3,
+		},
+		TraceFrame {
+			function: Some("outer".into()),
+			line: 8,
+		},
+	]);
+
+	let err = vm.call(outer, &[]).unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"RuntimeError: Expected 1 arguments but got 0."
+	);
+	let err = vm.call(Value::Nil, &[]).unwrap_err();
+	assert!(err
+		.to_string()
+		.contains("Can only call functions and classes"));
+
+	assert_eq!(
+		vm.call(outer, &[Value::Number(2.)])
+			.map(|_| ())
+			.unwrap_err()
+			.to_string(),
+		"RuntimeError: Binary operator `+` not applicable to values `2` and `nil`\n  |\n3 | \t\t\treturn n + nil;\n  | \t\t\t         ^\n[line 3] in inner()\n[line 8] in outer()"
+	);
+}
+
+#[test]
+fn natives_call_back_into_lox() {
+	fn twice(vm: &mut VM, args: &[Value]) -> Result<Value, Error> {
+		vm.call(args[0], &[])?;
+		vm.call(args[0], &[])
+	}
+
+	let (mut vm, output) = vm();
+	vm.define_native("twice", 1, twice);
+
+	vm.interpret(
+		r#"
+		var n = 0;
+		fun bump() {
+			n = n + 1;
+			return n;
+		}
+		print twice(bump);
+		print n;
+	"#
+		.into(),
+	)
+	.unwrap();
+	assert_eq!(output.take(), "2\n2\n");
+
+	let err = vm
+		.interpret(
+			r#"
+		fun fail() {
+			return -"oops";
+		}
+		twice(fail);
+	"#
+			.into(),
+		)
+		.unwrap_err();
+
+	let trace = match err.downcast_ref() {
+		Some(Error::Runtime(err)) => &err.trace,
+		other => panic!("Expected a runtime error, found {:?}", other),
+	};
+	assert_eq!(trace, &vec![
+		TraceFrame {
+			function: Some("fail".into()),
+			line: 3,
+		},
+		TraceFrame {
+			function: None,
+			line: 5,
+		},
+	]);
+
+	vm.interpret("print n;".into()).unwrap();
+	assert_eq!(output.take(), "2\n");
+}
+
+struct Counter {
+	count: u32,
+	on_bump: Value,
+}
+
+impl Userdata for Counter {
+	fn trace(&self, tracer: &mut Tracer) {
+		self.on_bump.trace(tracer);
+	}
+}
+
+#[test]
+fn userdata_methods() {
+	fn counter(vm: &mut VM, args: &[Value]) -> Result<Value, Error> {
+		let counter = Counter {
+			count: 0,
+			on_bump: args[0],
+		};
+
+		Ok(vm.host_userdata("Counter", counter).into())
+	}
+
+	fn bump(vm: &mut VM, args: &[Value]) -> Result<Value, Error> {
+		let counter = vm.userdata_mut::<Counter>(args[0]).unwrap();
+		counter.count += 1;
+
+		let (count, on_bump) = (counter.count, counter.on_bump);
+		vm.call(on_bump, &[(count as f64).into()])
+	}
+
+	fn count(vm: &mut VM, args: &[Value]) -> Result<Value, Error> {
+		let counter = vm.userdata::<Counter>(args[0]).unwrap();
+		Ok((counter.count as f64).into())
+	}
+
+	let (mut vm, output) = vm();
+	vm.define_native("counter", 1, counter);
+	vm.define_class("Counter", &[
+		Native {
+			name: "bump",
+			arity: 0,
+			function: bump,
+		},
+		Native {
+			name: "count",
+			arity: 0,
+			function: count,
+		},
+	]);
+
+	// The callback is only reachable through the userdata
+	vm.interpret(
+		r#"
+		fun makeCounter() {
+			var prefix = "bumped ";
+			fun report(n) {
+				print prefix + str(n);
+			}
+			return counter(report);
+		}
+
+		var c = makeCounter();
+		c.bump();
+		var bump = c.bump;
+		bump();
+		print c.count();
+		print c;
+		print type(c);
+	"#
+		.into(),
+	)
+	.unwrap();
+	assert_eq!(
+		output.take(),
+		"bumped 1\nbumped 2\n2\nCounter instance\ninstance\n"
+	);
+
+	for (src, message) in [
+		("c.count = 1;", "Userdata can't have fields"),
+		("c.reset();", "Undefined property `reset`"),
+		("c.count(1);", "Expected 0 arguments but got 1."),
+	] {
+		let err = vm.interpret(src.into()).unwrap_err();
+		assert!(
+			err.to_string().contains(message),
+			"Expected `{}` to contain `{}`",
+			err,
+			message
+		);
+	}
+}
+
+#[test]
+fn userdata_is_dropped() {
+	struct Handle {
+		_count: Rc<()>,
+	}
+	impl Userdata for Handle {}
+
+	let (mut vm, _) = vm();
+	let handle = Rc::new(());
+	vm.host_userdata("Handle", Handle {
+		_count: handle.clone(),
+	});
+	assert_eq!(Rc::strong_count(&handle), 2);
+
+	// Values handed to the host are released on the next call into the VM
+	vm.interpret("var s = \"garbage\" + \"!\";".into())
+		.unwrap();
+	assert_eq!(Rc::strong_count(&handle), 1);
+}
+
+#[test]
+fn stale_host_values() {
+	#[derive(Debug, PartialEq)]
+	struct Data(u32);
+	impl Userdata for Data {}
+
+	let (mut vm, _) = vm();
+	vm.interpret("fun f(s) { return s; }".into())
+		.unwrap();
+	let f = vm.global("f").unwrap();
+
+	let chars = "x".repeat(260);
+	let string = Value::Obj(vm.intern(chars.clone()));
+	let data = Value::Obj(vm.host_userdata("Data", Data(42)));
+	assert_eq!(vm.str(string), Some(chars.as_str()));
+	assert_eq!(vm.userdata::<Data>(data), Some(&Data(42)));
+	vm.userdata_mut::<Data>(data).unwrap().0 += 1;
+	assert_eq!(vm.userdata::<Data>(data), Some(&Data(43)));
+
+	// Neither is reachable from the script, so they're collected as soon as the VM
+	// allocates again
+	vm.interpret("var garbage = \"a\" + \"b\";".into())
+		.unwrap();
+	assert_eq!(vm.str(string), None);
+	assert_eq!(vm.userdata::<Data>(data), None);
+	assert_eq!(vm.userdata_mut::<Data>(data), None);
+	assert_eq!(vm.field(data, "x"), None);
+	assert_eq!(
+		String::from_lox(string, &vm),
+		Err(ConversionError::Type {
+			expected: "a string",
+			found: "a garbage-collected value".into(),
+		})
+	);
+	assert_eq!(
+		vm.call(f, &[string]).unwrap_err().to_string(),
+		"RuntimeError: Can't call with a value that's been garbage collected."
+	);
+}
+
+#[test]
+fn runtime_error_snippets() {
+	let (mut vm, _) = vm();
+
+	let err = vm
+		.interpret("var x = 1;\nprint -true;".into())
+		.unwrap_err();
+	let err = match err.downcast_ref() {
+		Some(Error::Runtime(err)) => err,
+		other => panic!("Expected a runtime error, found {:?}", other),
+	};
+
+	let snippet = err.snippet.as_ref().unwrap();
+	assert_eq!(snippet.text, "print -true;");
+	assert_eq!(
+		(snippet.span.start.character, snippet.span.end.character),
+		(6, 7)
+	);
+	assert_eq!(
+		err.to_string(),
+		"RuntimeError: Unary operator `-` not applicable to value: true\n  |\n2 | print -true;\n  |       ^\n[line 2] in script"
+	);
+
+	// Errors raised by the host outside of any script have nowhere to point to
+	let err = match vm.call(Value::Nil, &[]) {
+		Err(Error::Runtime(err)) => err,
+		other => panic!("Expected a runtime error, found {:?}", other),
+	};
+	assert_eq!(err.snippet, None);
+}
+
+fn compile_errors(vm: &mut VM, src: &str) -> Vec<super::Diagnostic> {
+	match vm.interpret(src.into()).unwrap_err().downcast() {
+		Ok(Error::Compile(diagnostics)) => diagnostics,
+		other => panic!("Expected a compile error, found {:?}", other),
+	}
+}
+
+#[test]
+fn compile_errors_are_all_reported() {
+	let (mut vm, output) = vm();
+
+	let diagnostics = compile_errors(
+		&mut vm,
+		"print 1 +;\nvar a = 1;\nreturn a;\nprint this;\na + 1 = 2;\nprint 2;",
+	);
+	let summary: Vec<_> = diagnostics
+		.iter()
+		.map(|d| (d.code, d.snippet.as_ref().unwrap().span.start.line))
+		.collect();
+	assert_eq!(summary, [
+		(Code::Syntax, 0),
+		(Code::InvalidContext, 2),
+		(Code::InvalidContext, 3),
+		(Code::InvalidAssignment, 4),
+	]);
+	assert!(diagnostics
+		.iter()
+		.all(|d| d.severity == Severity::Error));
+
+	// Nothing runs if any part of the script failed to compile
+	assert_eq!(output.take(), "");
+}
+
+#[test]
+fn compile_errors_recover_inside_functions() {
+	let (mut vm, _) = vm();
+
+	// Errors inside nested functions and classes don't throw off the scopes of the
+	// code that follows them
+	let diagnostics = compile_errors(
+		&mut vm,
+		r#"
+		fun f(a) { print a +; return a; }
+		class A {
+			method() {
+				var b = b;
+				{ var c; var c; }
+				return this;
+			}
+		}
+		fun g() { return this; }
+		{ var d = 1; print d; }
+		"#,
+	);
+	let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+	assert_eq!(codes, [
+		Code::Syntax,
+		Code::SelfReference,
+		Code::Redeclaration,
+		Code::InvalidContext,
+	]);
+
+	// The VM is still usable afterwards
+	vm.interpret("fun f(a) { return a; } print f(1);".into())
+		.unwrap();
+}
+
+#[test]
+fn compile_error_display() {
+	let (mut vm, _) = vm();
+
+	let err = vm
+		.interpret("var a = 1;\nprint a +;\nprint this;".into())
+		.unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"CompileError[E001]: Expected expression.\n  |\n2 | print a +;\n  |          ^\nCompileError[E005]: Can't use `this` outside of a method.\n  |\n3 | print this;\n  |       ^^^^"
+	);
+}
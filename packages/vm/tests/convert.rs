@@ -0,0 +1,115 @@
+use vm::{ConversionError, FromLox, IntoLox, Value, VM};
+
+#[derive(Debug, PartialEq, FromLox, IntoLox)]
+struct Point {
+	x: f64,
+	y: f64,
+}
+
+#[derive(Debug, PartialEq, FromLox, IntoLox)]
+struct Label {
+	text: String,
+	origin: Point,
+	size: Option<u32>,
+}
+
+#[test]
+fn primitives() {
+	let mut vm = VM::new();
+
+	let value = 42u8.into_lox(&mut vm);
+	assert_eq!(u8::from_lox(value, &vm).unwrap(), 42);
+	assert_eq!(f64::from_lox(value, &vm).unwrap(), 42.0);
+
+	let value = "hello".into_lox(&mut vm);
+	assert_eq!(String::from_lox(value, &vm).unwrap(), "hello");
+
+	assert_eq!(Option::<bool>::from_lox(Value::Nil, &vm).unwrap(), None);
+	assert_eq!(
+		Option::<bool>::from_lox(Value::Bool(true), &vm).unwrap(),
+		Some(true)
+	);
+}
+
+#[test]
+fn conversion_errors() {
+	let mut vm = VM::new();
+
+	let value = 300.into_lox(&mut vm);
+	assert!(matches!(
+		u8::from_lox(value, &vm),
+		Err(ConversionError::OutOfRange { .. })
+	));
+
+	let value = 1.5.into_lox(&mut vm);
+	assert!(i32::from_lox(value, &vm).is_err());
+
+	fn out_of_range<T>(result: Result<T, ConversionError>) -> bool {
+		matches!(result, Err(ConversionError::OutOfRange { .. }))
+	}
+
+	// `u64::MAX` and `i64::MAX` both round up to these as floats
+	let two_64 = 18_446_744_073_709_551_616.;
+	let two_63 = 9_223_372_036_854_775_808.;
+	assert!(out_of_range(u64::from_lox(two_64.into(), &vm)));
+	assert!(out_of_range(i64::from_lox(two_63.into(), &vm)));
+	assert_eq!(
+		u64::from_lox((two_64 - 2048.).into(), &vm).unwrap(),
+		u64::MAX - 2047
+	);
+	assert_eq!(i64::from_lox((-two_63).into(), &vm).unwrap(), i64::MIN);
+	assert!(out_of_range(i64::from_lox((-two_63 - 2048.).into(), &vm)));
+	assert!(out_of_range(u8::from_lox(256.0.into(), &vm)));
+	assert_eq!(u8::from_lox(255.0.into(), &vm).unwrap(), 255);
+
+	let err = bool::from_lox(Value::Nil, &vm).unwrap_err();
+	assert_eq!(err.to_string(), "Expected a bool, found `nil`");
+}
+
+#[test]
+fn structs_round_trip() {
+	let mut vm = VM::new();
+	let label = Label {
+		text: "origin".into(),
+		origin: Point { x: 0.0, y: 0.0 },
+		size: None,
+	};
+
+	let value = label.into_lox(&mut vm);
+	let label = Label::from_lox(value, &vm).unwrap();
+
+	assert_eq!(label.text, "origin");
+	assert_eq!(label.origin, Point { x: 0.0, y: 0.0 });
+	assert_eq!(label.size, None);
+}
+
+#[test]
+fn struct_errors() {
+	let mut vm = VM::new();
+
+	let err = Point::from_lox(Value::Number(1.0), &vm).unwrap_err();
+	assert_eq!(err.to_string(), "Expected a `Point` instance, found `1`");
+
+	vm.interpret(
+		"class Point { init(x) { this.x = x; } } fun make() { return Point(1); }".into(),
+	)
+	.unwrap();
+	let make = vm.global("make").unwrap();
+	let value = vm.call(make, &[]).unwrap();
+
+	let err = Point::from_lox(value, &vm).unwrap_err();
+	assert_eq!(err.to_string(), "Missing field `y`");
+}
+
+#[test]
+fn scripts_read_host_instances() {
+	let mut vm = VM::new();
+	vm.interpret("fun sum(point) { return point.x + point.y; }".into())
+		.unwrap();
+
+	let point = Point { x: 1.0, y: 2.0 }.into_lox(&mut vm);
+	let sum = vm.global("sum").unwrap();
+	let result = vm.call(sum, &[point]).unwrap();
+
+	assert_eq!(f64::from_lox(result, &vm).unwrap(), 3.0);
+}
@@ -0,0 +1,92 @@
+use std::{
+	io::Write,
+	process::{Command, Output, Stdio},
+};
+
+/// Runs the `vm` binary with the given arguments, piping `stdin` into it
+fn run(args: &[&str], stdin: &str) -> Output {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_vm"))
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+
+	child
+		.stdin
+		.take()
+		.unwrap()
+		.write_all(stdin.as_bytes())
+		.unwrap();
+
+	child.wait_with_output().unwrap()
+}
+
+fn text(bytes: &[u8]) -> String {
+	String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[test]
+fn piped_repl() {
+	let output = run(
+		&[],
+		"var a = 1;\nprint a + 1;\nprint -true;\nprint input();\nfrom input\nprint a;\n",
+	);
+
+	assert!(output.status.success());
+	assert_eq!(text(&output.stdout), "2\nfrom input\n1\n");
+	assert!(text(&output.stderr).starts_with("RuntimeError: Unary operator `-`"));
+}
+
+#[test]
+fn piped_repl_exit() {
+	let output = run(&["--plain"], "print 1;\nexit(3);\nprint 2;\n");
+
+	assert_eq!(output.status.code(), Some(3));
+	assert_eq!(text(&output.stdout), "1\n");
+}
+
+#[test]
+fn piped_repl_debug_output() {
+	let output = run(&["--debug=exec"], "print 1;\n");
+
+	// Debug output goes to stderr as plain text, leaving stdout to the script
+	assert!(output.status.success());
+	assert_eq!(text(&output.stdout), "1\n");
+	assert!(!text(&output.stderr).contains('\x1b'));
+	// The disassembler is only built into debug builds
+	if cfg!(debug_assertions) {
+		assert!(text(&output.stderr).contains("PRINT"));
+	}
+}
+
+#[test]
+fn script_from_stdin() {
+	let output = run(&["-", "first", "second"], "print argc();\nprint argv(1);\n");
+
+	assert!(output.status.success());
+	assert_eq!(text(&output.stdout), "2\nsecond\n");
+}
+
+#[test]
+fn script_exit_codes() {
+	let output = run(&["-"], "print 1 +;\nprint this;\n");
+	assert_eq!(output.status.code(), Some(65));
+	assert_eq!(
+		text(&output.stderr)
+			.matches("CompileError")
+			.count(),
+		2
+	);
+
+	let output = run(&["-"], "print 1;\nprint nope;\n");
+	assert_eq!(output.status.code(), Some(70));
+	assert_eq!(text(&output.stdout), "1\n");
+
+	let output = run(&["does/not/exist.lox"], "");
+	assert_eq!(output.status.code(), Some(74));
+
+	let output = run(&["--nope=1"], "");
+	assert_eq!(output.status.code(), Some(64));
+}